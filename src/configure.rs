@@ -22,6 +22,7 @@ use log::error;
 use serde_derive::{Deserialize, Serialize};
 #[cfg(feature = "spdlog-rs")]
 use spdlog::prelude::*;
+use std::collections::HashMap;
 use std::fmt::Debug;
 use std::path::Path;
 
@@ -32,8 +33,13 @@ pub struct ServerConfig {
     auth_header: Option<String>,
     public_status_page: bool,
     database_location: Option<String>,
+    resync_interval: Option<u64>,
+    history_retention_days: Option<u64>,
 }
 
+/// Uptime is reported for up to 90 days, a shorter history would cut that window.
+const MIN_HISTORY_RETENTION_DAYS: u64 = 90;
+
 impl ServerConfig {
     pub fn addr(&self) -> &str {
         &self.addr
@@ -56,6 +62,14 @@ impl ServerConfig {
             Some(ref location) => location.clone(),
         }
     }
+    /// Seconds between forced pushes of every component status to upstream.
+    pub fn resync_interval(&self) -> Option<u64> {
+        self.resync_interval
+    }
+    /// Days to keep status history, `None` keeps it forever.
+    pub fn history_retention_days(&self) -> Option<u64> {
+        self.history_retention_days
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -63,6 +77,27 @@ pub struct Configure {
     statuspage: StatusPageUpstream,
     components: Components,
     server: ServerConfig,
+    #[serde(default)]
+    uptime: UptimeConfig,
+    #[serde(default)]
+    webhook: Vec<WebhookUpstream>,
+    #[serde(default)]
+    slack: Vec<ChatNotifier>,
+    #[serde(default)]
+    discord: Vec<ChatNotifier>,
+    telegram: Option<TelegramNotifier>,
+    pagerduty: Option<PagerDutyUpstream>,
+    cachet: Option<CachetUpstream>,
+    uptime_kuma: Option<UptimeKumaUpstream>,
+    instatus: Option<InstatusUpstream>,
+    email: Option<EmailNotifier>,
+    matrix: Option<MatrixNotifier>,
+    #[serde(default)]
+    ntfy: NtfyUpstream,
+    #[serde(default)]
+    gotify: GotifyUpstream,
+    #[serde(default)]
+    hook: Vec<HookUpstream>,
 }
 
 impl Configure {
@@ -76,7 +111,7 @@ impl Configure {
             );
         }
         let context = context?;
-        let cfg = match toml::from_str(context.as_str()) {
+        let cfg: Configure = match toml::from_str(context.as_str()) {
             Ok(cfg) => cfg,
             Err(e) => {
                 error!(
@@ -87,15 +122,86 @@ impl Configure {
                 return Err(anyhow::Error::from(e));
             }
         };
+        if let Err(e) = cfg.validate() {
+            error!("Invalid configure {:?}: {}", path.as_ref().display(), e);
+            return Err(e);
+        }
         Ok(cfg)
     }
 
+    /// Reject values that decode fine but can not be used at runtime.
+    fn validate(&self) -> anyhow::Result<()> {
+        if self.server.resync_interval == Some(0) {
+            return Err(anyhow::anyhow!(
+                "server.resync_interval must be greater than 0, remove it to disable resync"
+            ));
+        }
+        if self.statuspage.reconcile_interval == Some(0) {
+            return Err(anyhow::anyhow!(
+                "statuspage.reconcile_interval must be greater than 0, remove it to reconcile only at startup"
+            ));
+        }
+        if self
+            .server
+            .history_retention_days
+            .is_some_and(|days| days < MIN_HISTORY_RETENTION_DAYS)
+        {
+            return Err(anyhow::anyhow!(
+                "server.history_retention_days must be at least {}, remove it to keep history forever",
+                MIN_HISTORY_RETENTION_DAYS
+            ));
+        }
+        Ok(())
+    }
+
     pub fn statuspage(&self) -> &StatusPageUpstream {
         &self.statuspage
     }
     pub fn server(&self) -> &ServerConfig {
         &self.server
     }
+    pub fn uptime(&self) -> &UptimeConfig {
+        &self.uptime
+    }
+    pub fn webhook(&self) -> &Vec<WebhookUpstream> {
+        &self.webhook
+    }
+    pub fn slack(&self) -> &Vec<ChatNotifier> {
+        &self.slack
+    }
+    pub fn discord(&self) -> &Vec<ChatNotifier> {
+        &self.discord
+    }
+    pub fn telegram(&self) -> Option<&TelegramNotifier> {
+        self.telegram.as_ref()
+    }
+    pub fn pagerduty(&self) -> Option<&PagerDutyUpstream> {
+        self.pagerduty.as_ref()
+    }
+    pub fn cachet(&self) -> Option<&CachetUpstream> {
+        self.cachet.as_ref()
+    }
+    pub fn uptime_kuma(&self) -> Option<&UptimeKumaUpstream> {
+        self.uptime_kuma.as_ref()
+    }
+    pub fn instatus(&self) -> Option<&InstatusUpstream> {
+        self.instatus.as_ref()
+    }
+    pub fn email(&self) -> Option<&EmailNotifier> {
+        self.email.as_ref()
+    }
+    pub fn matrix(&self) -> Option<&MatrixNotifier> {
+        self.matrix.as_ref()
+    }
+    pub fn ntfy(&self) -> &NtfyUpstream {
+        &self.ntfy
+    }
+    pub fn gotify(&self) -> &GotifyUpstream {
+        &self.gotify
+    }
+    pub fn hook(&self) -> &Vec<HookUpstream> {
+        &self.hook
+    }
 
     pub fn is_empty_services(&self) -> bool {
         self.components.0.is_empty()
@@ -103,6 +209,9 @@ impl Configure {
     pub fn components(&self) -> &Vec<Component> {
         &self.components.0
     }
+    pub fn component(&self, uuid: &str) -> Option<&Component> {
+        self.components.0.iter().find(|c| c.uuid() == uuid)
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -110,6 +219,15 @@ pub struct StatusPageUpstream {
     enabled: bool,
     #[serde(default)]
     oauth: String,
+    #[serde(default = "default_statuspage_url")]
+    url: String,
+    #[serde(default)]
+    authority: Authority,
+    reconcile_interval: Option<u64>,
+}
+
+fn default_statuspage_url() -> String {
+    "https://api.statuspage.io/".to_string()
 }
 
 impl StatusPageUpstream {
@@ -117,9 +235,449 @@ impl StatusPageUpstream {
         &self.oauth
     }
 
+    /// API base url, override for a mock server or an egress proxy path.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Side kept when local and statuspage.io component status differ.
+    pub fn authority(&self) -> Authority {
+        self.authority
+    }
+
+    /// Seconds between reconciliations after the one at startup.
+    pub fn reconcile_interval(&self) -> Option<u64> {
+        self.reconcile_interval
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Authority {
+    /// Push the status stored in database to statuspage.io.
+    #[default]
+    Local,
+    /// Store the status of statuspage.io and notify other upstreams.
+    Upstream,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct WebhookUpstream {
+    name: String,
+    url: String,
+    #[serde(default = "default_webhook_method")]
+    method: String,
+    #[serde(default)]
+    headers: HashMap<String, String>,
+    body: Option<String>,
+    secret: Option<String>,
+    #[serde(default = "default_webhook_retention_days")]
+    retention_days: u64,
+}
+
+fn default_webhook_method() -> String {
+    "POST".to_string()
+}
+
+fn default_webhook_retention_days() -> u64 {
+    30
+}
+
+impl WebhookUpstream {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+    pub fn method(&self) -> &str {
+        &self.method
+    }
+    pub fn headers(&self) -> &HashMap<String, String> {
+        &self.headers
+    }
+    /// Body template, see [`crate::datastructures::StatusChange::render`] for placeholders.
+    pub fn body(&self) -> Option<&str> {
+        self.body.as_deref()
+    }
+    /// Key of the HMAC-SHA256 signature, deliveries are unsigned if unset.
+    pub fn secret(&self) -> Option<&str> {
+        self.secret.as_deref()
+    }
+    /// Days to keep recorded delivery attempts.
+    pub fn retention_days(&self) -> u64 {
+        self.retention_days
+    }
+}
+
+/// Incoming webhook of a chat service.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct ChatNotifier {
+    name: Option<String>,
+    url: String,
+    #[serde(default)]
+    components: Vec<String>,
+}
+
+impl ChatNotifier {
+    /// Upstream name, should be set if more than one block of a service is configured.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+    /// Component uuids routed to this webhook, empty for every component.
+    pub fn components(&self) -> &Vec<String> {
+        &self.components
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct TelegramNotifier {
+    bot_token: String,
+    #[serde(default = "default_telegram_api_url")]
+    api_url: String,
+    #[serde(default)]
+    chat_ids: Vec<String>,
+    #[serde(default)]
+    components: HashMap<String, Vec<String>>,
+}
+
+fn default_telegram_api_url() -> String {
+    "https://api.telegram.org".to_string()
+}
+
+impl TelegramNotifier {
+    pub fn bot_token(&self) -> &str {
+        &self.bot_token
+    }
+    /// Bot API server, override for a self-hosted one.
+    pub fn api_url(&self) -> &str {
+        &self.api_url
+    }
+    /// Chats of components without their own entry in `components`.
+    pub fn chat_ids(&self) -> &Vec<String> {
+        &self.chat_ids
+    }
+    /// Chats of each component uuid.
+    pub fn components(&self) -> &HashMap<String, Vec<String>> {
+        &self.components
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PagerDutyUpstream {
+    routing_key: String,
+    #[serde(default = "default_pagerduty_url")]
+    url: String,
+    #[serde(default = "default_pagerduty_severity")]
+    severity: HashMap<String, String>,
+}
+
+fn default_pagerduty_url() -> String {
+    "https://events.pagerduty.com/v2/enqueue".to_string()
+}
+
+fn default_pagerduty_severity() -> HashMap<String, String> {
+    HashMap::from([("major_outage".to_string(), "critical".to_string())])
+}
+
+impl PagerDutyUpstream {
+    /// Integration key of the Events API v2 service.
+    pub fn routing_key(&self) -> &str {
+        &self.routing_key
+    }
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+    /// Component status that triggers an alert, mapped to the alert severity.
+    pub fn severity(&self) -> &HashMap<String, String> {
+        &self.severity
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct CachetUpstream {
+    url: String,
+    token: String,
+    #[serde(default)]
+    components: HashMap<String, u64>,
+}
+
+impl CachetUpstream {
+    /// Base url of the Cachet installation.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+    pub fn token(&self) -> &str {
+        &self.token
+    }
+    /// Cachet component id of each component uuid.
+    pub fn components(&self) -> &HashMap<String, u64> {
+        &self.components
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct UptimeKumaUpstream {
+    url: String,
+    #[serde(default)]
+    components: HashMap<String, String>,
+}
+
+impl UptimeKumaUpstream {
+    /// Base url of the Uptime Kuma instance.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+    /// Push monitor token of each component uuid.
+    pub fn components(&self) -> &HashMap<String, String> {
+        &self.components
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct InstatusUpstream {
+    api_key: String,
+    page_id: String,
+    #[serde(default = "default_instatus_url")]
+    url: String,
+    #[serde(default)]
+    components: HashMap<String, String>,
+}
+
+fn default_instatus_url() -> String {
+    "https://api.instatus.com".to_string()
+}
+
+impl InstatusUpstream {
+    pub fn api_key(&self) -> &str {
+        &self.api_key
+    }
+    pub fn page_id(&self) -> &str {
+        &self.page_id
+    }
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+    /// Instatus component id of each component uuid.
+    pub fn components(&self) -> &HashMap<String, String> {
+        &self.components
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct EmailNotifier {
+    host: String,
+    #[serde(default = "default_smtp_port")]
+    port: u16,
+    #[serde(default = "default_true")]
+    starttls: bool,
+    username: Option<String>,
+    password: Option<String>,
+    from: String,
+    #[serde(default)]
+    recipients: Vec<String>,
+    public_url: Option<String>,
+    #[serde(default)]
+    components: HashMap<String, Vec<String>>,
+}
+
+fn default_smtp_port() -> u16 {
+    587
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl EmailNotifier {
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+    /// Upgrade the connection with STARTTLS, plain text if disabled.
+    pub fn starttls(&self) -> bool {
+        self.starttls
+    }
+    pub fn username(&self) -> Option<&str> {
+        self.username.as_deref()
+    }
+    pub fn password(&self) -> Option<&str> {
+        self.password.as_deref()
+    }
+    pub fn from(&self) -> &str {
+        &self.from
+    }
+    /// Recipients of components without their own entry in `components`.
+    pub fn recipients(&self) -> &Vec<String> {
+        &self.recipients
+    }
+    /// Public status page linked in every message.
+    pub fn public_url(&self) -> Option<&str> {
+        self.public_url.as_deref()
+    }
+    /// Recipients of each component uuid.
+    pub fn components(&self) -> &HashMap<String, Vec<String>> {
+        &self.components
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct MatrixNotifier {
+    homeserver: String,
+    access_token: String,
+    #[serde(default)]
+    rooms: Vec<String>,
+    #[serde(default)]
+    components: HashMap<String, Vec<String>>,
+}
+
+impl MatrixNotifier {
+    /// Client-server API base url, e.g. `https://matrix.example.com`.
+    pub fn homeserver(&self) -> &str {
+        &self.homeserver
+    }
+    pub fn access_token(&self) -> &str {
+        &self.access_token
+    }
+    /// Room ids of components without their own entry in `components`.
+    pub fn rooms(&self) -> &Vec<String> {
+        &self.rooms
+    }
+    /// Room ids of each component uuid.
+    pub fn components(&self) -> &HashMap<String, Vec<String>> {
+        &self.components
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct NtfyUpstream {
+    enabled: bool,
+    #[serde(default = "default_ntfy_url")]
+    url: String,
+    #[serde(default)]
+    topic: String,
+    #[serde(default)]
+    token: String,
+}
+
+fn default_ntfy_url() -> String {
+    "https://ntfy.sh".to_string()
+}
+
+impl NtfyUpstream {
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+    pub fn topic(&self) -> &str {
+        &self.topic
+    }
+    /// Access token of a protected topic, empty for anonymous publish.
+    pub fn token(&self) -> &str {
+        &self.token
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct GotifyUpstream {
+    enabled: bool,
+    #[serde(default)]
+    url: String,
+    #[serde(default)]
+    token: String,
+}
+
+impl GotifyUpstream {
     pub fn enabled(&self) -> bool {
         self.enabled
     }
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+    /// Application token.
+    pub fn token(&self) -> &str {
+        &self.token
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct HookUpstream {
+    name: String,
+    command: String,
+    #[serde(default)]
+    args: Vec<String>,
+    #[serde(default = "default_hook_timeout")]
+    timeout: u64,
+    #[serde(default)]
+    components: Vec<String>,
+}
+
+fn default_hook_timeout() -> u64 {
+    30
+}
+
+impl HookUpstream {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    /// Program to execute, looked up in `PATH` if not a path.
+    pub fn command(&self) -> &str {
+        &self.command
+    }
+    pub fn args(&self) -> &Vec<String> {
+        &self.args
+    }
+    /// Seconds before the program is killed.
+    pub fn timeout(&self) -> u64 {
+        self.timeout
+    }
+    /// Component uuids running this hook, empty for every component.
+    pub fn components(&self) -> &Vec<String> {
+        &self.components
+    }
+}
+
+/// Fraction of time counted as downtime while a component is in each non-operational state,
+/// `major_outage` always counts as full downtime.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct UptimeConfig {
+    #[serde(default)]
+    degraded_performance: f64,
+    #[serde(default = "default_partial_outage_weight")]
+    partial_outage: f64,
+}
+
+fn default_partial_outage_weight() -> f64 {
+    0.3
+}
+
+impl Default for UptimeConfig {
+    fn default() -> Self {
+        Self {
+            degraded_performance: 0.0,
+            partial_outage: default_partial_outage_weight(),
+        }
+    }
+}
+
+impl UptimeConfig {
+    pub fn degraded_performance(&self) -> f64 {
+        self.degraded_performance
+    }
+    pub fn partial_outage(&self) -> f64 {
+        self.partial_outage
+    }
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize)]
@@ -133,6 +691,10 @@ pub struct Component {
     identity_id: String,
     #[serde(default)]
     page: String,
+    #[serde(default)]
+    token: String,
+    heartbeat_timeout: Option<u64>,
+    heartbeat_status: Option<String>,
 }
 
 impl Component {
@@ -144,12 +706,21 @@ impl Component {
         &self.page
     }
 
-    pub fn new(uuid: String, name: String, identity_id: String, page: String) -> Self {
+    pub fn new(
+        uuid: String,
+        name: String,
+        identity_id: String,
+        page: String,
+        token: String,
+    ) -> Self {
         Self {
             uuid,
             name,
             identity_id,
             page,
+            token,
+            heartbeat_timeout: None,
+            heartbeat_status: None,
         }
     }
 
@@ -161,6 +732,22 @@ impl Component {
         &self.name
     }
 
+    pub fn token(&self) -> &str {
+        &self.token
+    }
+
+    /// Seconds without report before the component is marked as [`Self::heartbeat_status`].
+    pub fn heartbeat_timeout(&self) -> Option<u64> {
+        self.heartbeat_timeout
+    }
+
+    pub fn heartbeat_status(&self) -> &str {
+        match self.heartbeat_status {
+            None => "major_outage",
+            Some(ref status) => status,
+        }
+    }
+
     pub fn need_push(&self) -> bool {
         !self.identity_id.is_empty() && !self.page.is_empty()
     }
@@ -171,8 +758,46 @@ impl From<FetchReturnType> for Component {
         Self {
             uuid: ret.0,
             name: "".to_string(),
-            identity_id: ret.2.unwrap_or_else(|| "".to_string()),
-            page: ret.1.unwrap_or_else(|| "".to_string()),
+            identity_id: ret.2.unwrap_or_default(),
+            page: ret.1.unwrap_or_default(),
+            token: ret.3.unwrap_or_default(),
+            heartbeat_timeout: None,
+            heartbeat_status: None,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(server: &str, statuspage: &str) -> Configure {
+        toml::from_str(&format!(
+            "components = []\n\
+            [statuspage]\nenabled = false\noauth = \"\"\n{}\n\
+            [server]\naddr = \"127.0.0.1\"\nport = 41132\nauth_header = \"\"\npublic_status_page = false\n{}\n",
+            statuspage, server
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_validate_resync_interval() {
+        assert!(parse("", "").validate().is_ok());
+        assert!(parse("resync_interval = 60", "").validate().is_ok());
+        assert!(parse("resync_interval = 0", "").validate().is_err());
+    }
+
+    #[test]
+    fn test_validate_reconcile_interval() {
+        assert!(parse("", "reconcile_interval = 3600").validate().is_ok());
+        assert!(parse("", "reconcile_interval = 0").validate().is_err());
+    }
+
+    #[test]
+    fn test_validate_history_retention_days() {
+        assert!(parse("history_retention_days = 365", "").validate().is_ok());
+        assert!(parse("history_retention_days = 90", "").validate().is_ok());
+        assert!(parse("history_retention_days = 30", "").validate().is_err());
+    }
+}
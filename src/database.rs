@@ -1,19 +1,296 @@
+use crate::configure::Configure;
+use anyhow::anyhow;
+#[cfg(any(feature = "env_logger", feature = "log4rs"))]
+use log::{error, info};
+use sha2::{Digest, Sha256};
+#[cfg(feature = "spdlog-rs")]
+use spdlog::prelude::*;
+use sqlx::{Executor, SqliteConnection};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+
+const DAY: u64 = 24 * 3600;
+const PRUNE_INTERVAL: Duration = Duration::from_secs(3600);
+
 pub mod v1 {
+    pub const VERSION: &str = "1";
+}
+
+pub mod v2 {
+    pub const MIGRATE_FROM_V1: &str = r#"ALTER TABLE "machines" ADD COLUMN "token" TEXT;
+        CREATE TABLE IF NOT EXISTS "upstream_meta" (
+            "key"	TEXT NOT NULL,
+            "value"	TEXT NOT NULL,
+            PRIMARY KEY("key")
+        );
+        INSERT OR REPLACE INTO "upstream_meta" VALUES ('version', '2');
+        "#;
+
+    pub const VERSION: &str = "2";
+}
+
+pub mod v3 {
+    pub const MIGRATE_FROM_V2: &str = r#"CREATE TABLE "history" (
+            "id"	INTEGER NOT NULL,
+            "uuid"	TEXT NOT NULL,
+            "old_status"	TEXT NOT NULL,
+            "new_status"	TEXT NOT NULL,
+            "timestamp"	INTEGER NOT NULL,
+            "source"	TEXT NOT NULL,
+            PRIMARY KEY("id" AUTOINCREMENT)
+        );
+        CREATE INDEX "history_uuid_timestamp" ON "history" ("uuid", "timestamp");
+        UPDATE "upstream_meta" SET "value" = '3' WHERE "key" = 'version';
+        "#;
+
+    pub const VERSION: &str = "3";
+}
+
+pub mod v4 {
+    pub const MIGRATE_FROM_V3: &str = r#"CREATE TABLE "outbox" (
+            "id"	INTEGER NOT NULL,
+            "uuid"	TEXT NOT NULL,
+            "page"	TEXT NOT NULL,
+            "component_id"	TEXT NOT NULL,
+            "status"	TEXT NOT NULL,
+            "attempts"	INTEGER NOT NULL DEFAULT 0,
+            "next_attempt"	INTEGER NOT NULL,
+            "last_error"	TEXT,
+            "created"	INTEGER NOT NULL,
+            PRIMARY KEY("id" AUTOINCREMENT)
+        );
+        UPDATE "upstream_meta" SET "value" = '4' WHERE "key" = 'version';
+        "#;
+
+    pub const VERSION: &str = "4";
+}
+
+pub mod v5 {
+    pub const MIGRATE_FROM_V4: &str = r#"ALTER TABLE "outbox" ADD COLUMN "upstream" TEXT NOT NULL DEFAULT 'statuspage';
+        UPDATE "upstream_meta" SET "value" = '5' WHERE "key" = 'version';
+        "#;
+
+    pub const VERSION: &str = "5";
+}
+
+pub mod v6 {
+    pub const MIGRATE_FROM_V5: &str = r#"ALTER TABLE "outbox" ADD COLUMN "name" TEXT NOT NULL DEFAULT '';
+        ALTER TABLE "outbox" ADD COLUMN "old_status" TEXT NOT NULL DEFAULT 'unknown';
+        UPDATE "upstream_meta" SET "value" = '6' WHERE "key" = 'version';
+        "#;
+
+    pub const VERSION: &str = "6";
+}
+
+pub mod v7 {
     pub const CREATE_TABLE: &str = r#"CREATE TABLE "machines" (
             "uuid"	TEXT NOT NULL,
             "status"	TEXT NOT NULL,
             "last_update"	INTEGER NOT NULL,
+            "need_push"	INTEGER NOT NULL,
             "page"   TEXT,
             "component_id" TEXT,
+            "token" TEXT,
+            PRIMARY KEY("uuid")
         );
+        CREATE TABLE "history" (
+            "id"	INTEGER NOT NULL,
+            "uuid"	TEXT NOT NULL,
+            "old_status"	TEXT NOT NULL,
+            "new_status"	TEXT NOT NULL,
+            "timestamp"	INTEGER NOT NULL,
+            "source"	TEXT NOT NULL,
+            PRIMARY KEY("id" AUTOINCREMENT)
+        );
+        CREATE INDEX "history_uuid_timestamp" ON "history" ("uuid", "timestamp");
+        CREATE TABLE "outbox" (
+            "id"	INTEGER NOT NULL,
+            "uuid"	TEXT NOT NULL,
+            "page"	TEXT NOT NULL,
+            "component_id"	TEXT NOT NULL,
+            "status"	TEXT NOT NULL,
+            "attempts"	INTEGER NOT NULL DEFAULT 0,
+            "next_attempt"	INTEGER NOT NULL,
+            "last_error"	TEXT,
+            "created"	INTEGER NOT NULL,
+            "upstream"	TEXT NOT NULL,
+            "name"	TEXT NOT NULL,
+            "old_status"	TEXT NOT NULL,
+            PRIMARY KEY("id" AUTOINCREMENT)
+        );
+        CREATE TABLE "webhook_deliveries" (
+            "id"	INTEGER NOT NULL,
+            "delivery_id"	TEXT NOT NULL,
+            "upstream"	TEXT NOT NULL,
+            "uuid"	TEXT NOT NULL,
+            "new_status"	TEXT NOT NULL,
+            "attempt"	INTEGER NOT NULL,
+            "status_code"	INTEGER,
+            "error"	TEXT,
+            "timestamp"	INTEGER NOT NULL,
+            PRIMARY KEY("id" AUTOINCREMENT)
+        );
+        CREATE INDEX "webhook_deliveries_delivery_id" ON "webhook_deliveries" ("delivery_id");
         CREATE TABLE "upstream_meta" (
             "key"	TEXT NOT NULL,
             "value"	TEXT NOT NULL,
             PRIMARY KEY("key")
         );
-        INSERT INTO "fdc_meta" VALUES ("version", "1");
+        INSERT INTO "upstream_meta" VALUES ('version', '7');
         "#;
-    pub const VERSION: &str = "1";
+
+    pub const MIGRATE_FROM_V6: &str = r#"CREATE TABLE "webhook_deliveries" (
+            "id"	INTEGER NOT NULL,
+            "delivery_id"	TEXT NOT NULL,
+            "upstream"	TEXT NOT NULL,
+            "uuid"	TEXT NOT NULL,
+            "new_status"	TEXT NOT NULL,
+            "attempt"	INTEGER NOT NULL,
+            "status_code"	INTEGER,
+            "error"	TEXT,
+            "timestamp"	INTEGER NOT NULL,
+            PRIMARY KEY("id" AUTOINCREMENT)
+        );
+        CREATE INDEX "webhook_deliveries_delivery_id" ON "webhook_deliveries" ("delivery_id");
+        UPDATE "upstream_meta" SET "value" = '7' WHERE "key" = 'version';
+        "#;
+
+    pub const VERSION: &str = "7";
+}
+
+pub use v7 as current;
+
+/// Create tables on an empty database, or bring an older database up to [`current::VERSION`].
+pub async fn init_database(conn: &mut SqliteConnection) -> anyhow::Result<()> {
+    let exists = sqlx::query_as::<_, (i32,)>(
+        r#"SELECT 1 FROM "sqlite_master" WHERE "type" = 'table' AND "name" = 'machines'"#,
+    )
+    .fetch_optional(&mut *conn)
+    .await
+    .map_err(|e| anyhow!("Query database tables error: {:?}", e))?;
+
+    if exists.is_none() {
+        conn.execute(current::CREATE_TABLE)
+            .await
+            .map_err(|e| anyhow!("Create database tables error: {:?}", e))?;
+        return Ok(());
+    }
+
+    // Databases created before the meta table existed are treated as version 1.
+    let version = sqlx::query_as::<_, (String,)>(
+        r#"SELECT "value" FROM "upstream_meta" WHERE "key" = 'version'"#,
+    )
+    .fetch_optional(&mut *conn)
+    .await
+    .ok()
+    .flatten()
+    .map(|(version,)| version)
+    .unwrap_or_else(|| v1::VERSION.to_string());
+
+    if version == current::VERSION {
+        return Ok(());
+    }
+
+    let migrations = [
+        (v1::VERSION, v2::MIGRATE_FROM_V1),
+        (v2::VERSION, v3::MIGRATE_FROM_V2),
+        (v3::VERSION, v4::MIGRATE_FROM_V3),
+        (v4::VERSION, v5::MIGRATE_FROM_V4),
+        (v5::VERSION, v6::MIGRATE_FROM_V5),
+        (v6::VERSION, v7::MIGRATE_FROM_V6),
+    ];
+    let mut upgrading = false;
+    for (from, migration) in migrations {
+        if upgrading || version == from {
+            upgrading = true;
+            conn.execute(migration)
+                .await
+                .map_err(|e| anyhow!("Migrate database from version {} error: {:?}", from, e))?;
+        }
+    }
+    Ok(())
+}
+
+/// Component tokens are stored as SHA-256 hex digest, the plain token is never kept.
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// Record a status report of component `uuid` in the history table.
+pub async fn insert_history(
+    conn: &mut SqliteConnection,
+    uuid: &str,
+    old_status: &str,
+    new_status: &str,
+    source: &str,
+) -> anyhow::Result<()> {
+    sqlx::query(
+        r#"INSERT INTO "history" ("uuid", "old_status", "new_status", "timestamp", "source")
+            VALUES (?, ?, ?, ?, ?)"#,
+    )
+    .bind(uuid)
+    .bind(old_status)
+    .bind(new_status)
+    .bind(get_current_timestamp() as i64)
+    .bind(source)
+    .execute(conn)
+    .await
+    .map_err(|e| anyhow!("Insert history of {} error: {:?}", uuid, e))?;
+    Ok(())
+}
+
+/// Delete history recorded more than `retention_days` ago, returns the number of rows deleted.
+pub async fn prune_history(
+    conn: &mut SqliteConnection,
+    retention_days: u64,
+) -> anyhow::Result<u64> {
+    let ret = sqlx::query(r#"DELETE FROM "history" WHERE "timestamp" < ?"#)
+        .bind(get_current_timestamp().saturating_sub(retention_days * DAY) as i64)
+        .execute(conn)
+        .await
+        .map_err(|e| anyhow!("Prune history error: {:?}", e))?;
+    Ok(ret.rows_affected())
+}
+
+/// Spawn hourly pruning of history, returns `None` if `history_retention_days` is unset.
+pub fn spawn_prune_history(
+    config: &Configure,
+    conn: Arc<Mutex<SqliteConnection>>,
+) -> Option<JoinHandle<()>> {
+    let retention_days = config.server().history_retention_days()?;
+    Some(tokio::spawn(async move {
+        let mut interval = tokio::time::interval(PRUNE_INTERVAL);
+        loop {
+            interval.tick().await;
+            match prune_history(&mut *conn.lock().await, retention_days).await {
+                Ok(0) => {}
+                Ok(deleted) => info!("Prune {} history row(s)", deleted),
+                Err(e) => error!("Got error while prune history: {:?}", e),
+            }
+        }
+    }))
+}
+
+/// Timestamp of the last transition away from `operational` at or before `until`,
+/// i.e. when the ongoing incident of a component started.
+pub async fn incident_started(
+    conn: &mut SqliteConnection,
+    uuid: &str,
+    until: u64,
+) -> anyhow::Result<Option<u64>> {
+    let ret = sqlx::query_as::<_, (i64,)>(
+        r#"SELECT "timestamp" FROM "history"
+            WHERE "uuid" = ? AND "old_status" = 'operational' AND "new_status" != 'operational'
+            AND "timestamp" <= ? ORDER BY "timestamp" DESC, "id" DESC LIMIT 1"#,
+    )
+    .bind(uuid)
+    .bind(until as i64)
+    .fetch_optional(conn)
+    .await
+    .map_err(|e| anyhow!("Fetch incident start of {} error: {:?}", uuid, e))?;
+    Ok(ret.map(|(timestamp,)| timestamp as u64))
 }
 
 pub fn get_current_timestamp() -> u64 {
@@ -23,3 +300,34 @@ pub fn get_current_timestamp() -> u64 {
         .expect("Time went backwards");
     since_the_epoch.as_secs()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::memory_database;
+
+    #[tokio::test]
+    async fn test_prune_history() {
+        let mut conn = memory_database().await;
+        let now = get_current_timestamp() as i64;
+        for timestamp in [now - 100 * DAY as i64, now - 10 * DAY as i64, now] {
+            sqlx::query(
+                r#"INSERT INTO "history" ("uuid", "old_status", "new_status", "timestamp", "source")
+                    VALUES ('a', 'operational', 'major_outage', ?, 'report')"#,
+            )
+            .bind(timestamp)
+            .execute(&mut conn)
+            .await
+            .unwrap();
+        }
+
+        assert_eq!(prune_history(&mut conn, 90).await.unwrap(), 1);
+        assert_eq!(prune_history(&mut conn, 90).await.unwrap(), 0);
+        assert_eq!(prune_history(&mut conn, 5).await.unwrap(), 1);
+        let (left,) = sqlx::query_as::<_, (i64,)>(r#"SELECT COUNT(*) FROM "history""#)
+            .fetch_one(&mut conn)
+            .await
+            .unwrap();
+        assert_eq!(left, 1);
+    }
+}
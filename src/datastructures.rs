@@ -1,7 +1,11 @@
+use crate::configure::Component;
 use crate::statuspagelib::ComponentStatus;
+use crate::uptime::UptimeData;
+use anyhow::anyhow;
 use async_trait::async_trait;
 use serde_derive::{Deserialize, Serialize};
 use std::fmt::Formatter;
+use std::sync::Arc;
 
 #[derive(Clone, Debug, Default, Deserialize, Serialize)]
 pub struct TransferData {
@@ -23,7 +27,171 @@ impl TransferData {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct ComponentData {
+    uuid: String,
+    name: String,
+    page: String,
+    identity_id: String,
+    status: String,
+    last_update: i64,
+    uptime: UptimeData,
+}
+
+impl ComponentData {
+    pub fn new(
+        uuid: String,
+        name: String,
+        page: String,
+        identity_id: String,
+        status: String,
+        last_update: i64,
+    ) -> Self {
+        Self {
+            uuid,
+            name,
+            page,
+            identity_id,
+            status,
+            last_update,
+            uptime: Default::default(),
+        }
+    }
+
+    pub fn with_uptime(mut self, uptime: UptimeData) -> Self {
+        self.uptime = uptime;
+        self
+    }
+}
+
+/// Query string of the component list, `status` accepts comma separated values.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct ListQuery {
+    status: Option<String>,
+}
+
+impl ListQuery {
+    pub fn matches(&self, status: &str) -> bool {
+        match self.status {
+            None => true,
+            Some(ref filter) => filter.split(',').any(|s| s.trim() == status),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct HistoryData {
+    old_status: String,
+    new_status: String,
+    timestamp: i64,
+    source: String,
+}
+
+impl From<(String, String, i64, String)> for HistoryData {
+    fn from((old_status, new_status, timestamp, source): (String, String, i64, String)) -> Self {
+        Self {
+            old_status,
+            new_status,
+            timestamp,
+            source,
+        }
+    }
+}
+
+/// Query string of the history endpoint, both bounds are inclusive unix timestamps.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct HistoryQuery {
+    since: Option<i64>,
+    until: Option<i64>,
+}
+
+impl HistoryQuery {
+    pub fn since(&self) -> i64 {
+        self.since.unwrap_or(0)
+    }
+    pub fn until(&self) -> i64 {
+        self.until.unwrap_or(i64::MAX)
+    }
+}
+
+pub type DeliveryRow = (
+    String,
+    String,
+    String,
+    String,
+    u32,
+    Option<u16>,
+    Option<String>,
+    i64,
+);
+
+/// A single attempt of a webhook delivery.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct DeliveryData {
+    delivery_id: String,
+    upstream: String,
+    uuid: String,
+    new_status: String,
+    attempt: u32,
+    status_code: Option<u16>,
+    error: Option<String>,
+    timestamp: i64,
+}
+
+impl From<DeliveryRow> for DeliveryData {
+    fn from(
+        (delivery_id, upstream, uuid, new_status, attempt, status_code, error, timestamp): DeliveryRow,
+    ) -> Self {
+        Self {
+            delivery_id,
+            upstream,
+            uuid,
+            new_status,
+            attempt,
+            status_code,
+            error,
+            timestamp,
+        }
+    }
+}
+
+/// Query string of the webhook deliveries endpoint, newest attempts are returned first.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct DeliveryQuery {
+    upstream: Option<String>,
+    uuid: Option<String>,
+    delivery_id: Option<String>,
+    limit: Option<u32>,
+}
+
+impl DeliveryQuery {
+    pub fn upstream(&self) -> Option<&str> {
+        self.upstream.as_deref()
+    }
+    pub fn uuid(&self) -> Option<&str> {
+        self.uuid.as_deref()
+    }
+    pub fn delivery_id(&self) -> Option<&str> {
+        self.delivery_id.as_deref()
+    }
+    pub fn limit(&self) -> u32 {
+        self.limit.unwrap_or(100).min(1000)
+    }
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct TokenData {
+    #[serde(default)]
+    token: String,
+}
+
+impl TokenData {
+    pub fn token(&self) -> &str {
+        &self.token
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum ServerLastStatus {
     Optional,
     Outage,
@@ -54,6 +222,38 @@ impl TryFrom<&str> for ServerLastStatus {
     }
 }
 
+impl ServerLastStatus {
+    pub const VARIANTS: [ServerLastStatus; 5] = [
+        ServerLastStatus::Optional,
+        ServerLastStatus::Outage,
+        ServerLastStatus::DegradedPerformance,
+        ServerLastStatus::PartialOutage,
+        ServerLastStatus::Unknown,
+    ];
+
+    /// Indicator colour used by the public status page.
+    pub fn colour(&self) -> &'static str {
+        match self {
+            ServerLastStatus::Optional => "#2fcc66",
+            ServerLastStatus::Outage => "#e74c3c",
+            ServerLastStatus::DegradedPerformance => "#f1c40f",
+            ServerLastStatus::PartialOutage => "#e67e22",
+            ServerLastStatus::Unknown => "#95a5a6",
+        }
+    }
+
+    /// Short wording used in notification messages.
+    pub fn label(&self) -> &'static str {
+        match self {
+            ServerLastStatus::Optional => "operational",
+            ServerLastStatus::Outage => "major outage",
+            ServerLastStatus::DegradedPerformance => "degraded",
+            ServerLastStatus::PartialOutage => "partial outage",
+            ServerLastStatus::Unknown => "unknown",
+        }
+    }
+}
+
 impl std::fmt::Display for ServerLastStatus {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         write!(
@@ -70,34 +270,181 @@ impl std::fmt::Display for ServerLastStatus {
     }
 }
 
-#[async_trait]
-pub trait UpstreamTrait: Send + Sync {
-    #[deprecated]
-    async fn get_component_status(&self, component: &str, page: &str) -> anyhow::Result<()>;
+/// A status transition of one component, delivered to every upstream.
+#[derive(Clone, Debug)]
+pub struct StatusChange {
+    uuid: String,
+    name: String,
+    page: String,
+    component_id: String,
+    old_status: ServerLastStatus,
+    new_status: ServerLastStatus,
+    timestamp: u64,
+}
 
-    async fn set_component_status(
-        &self,
-        component: &str,
-        page: &str,
-        status: ComponentStatus,
-    ) -> anyhow::Result<()>;
+impl StatusChange {
+    pub fn new(
+        component: &Component,
+        old_status: ServerLastStatus,
+        new_status: ServerLastStatus,
+        timestamp: u64,
+    ) -> Self {
+        Self {
+            uuid: component.uuid().to_string(),
+            name: component.name().to_string(),
+            page: component.page().to_string(),
+            component_id: component.report_id().to_string(),
+            old_status,
+            new_status,
+            timestamp,
+        }
+    }
+
+    pub fn uuid(&self) -> &str {
+        &self.uuid
+    }
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    pub fn page(&self) -> &str {
+        &self.page
+    }
+    /// statuspage.io component id, `identity_id` in configure file.
+    pub fn component_id(&self) -> &str {
+        &self.component_id
+    }
+    pub fn old_status(&self) -> ServerLastStatus {
+        self.old_status
+    }
+    pub fn new_status(&self) -> ServerLastStatus {
+        self.new_status
+    }
+    pub fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+    /// Status page counterpart of the new status, `None` for unknown status.
+    pub fn status(&self) -> Option<ComponentStatus> {
+        self.new_status.try_into().ok()
+    }
+
+    /// Replace `{{uuid}}`, `{{name}}`, `{{page}}`, `{{component_id}}`, `{{old_status}}`,
+    /// `{{new_status}}` and `{{timestamp}}` in template, every value passes through `escape`.
+    /// Unknown placeholders are kept as is.
+    pub fn render<F: Fn(&str) -> String>(&self, template: &str, escape: F) -> String {
+        let mut result = String::with_capacity(template.len());
+        let mut rest = template;
+        while let Some(start) = rest.find("{{") {
+            result.push_str(&rest[..start]);
+            let after = &rest[start + 2..];
+            let end = match after.find("}}") {
+                Some(end) => end,
+                None => {
+                    result.push_str(&rest[start..]);
+                    rest = "";
+                    break;
+                }
+            };
+            let value = match after[..end].trim() {
+                "uuid" => self.uuid.clone(),
+                "name" => self.name.clone(),
+                "page" => self.page.clone(),
+                "component_id" => self.component_id.clone(),
+                "old_status" => self.old_status.to_string(),
+                "new_status" => self.new_status.to_string(),
+                "timestamp" => self.timestamp.to_string(),
+                _ => {
+                    result.push_str(&rest[start..start + end + 4]);
+                    rest = &after[end + 2..];
+                    continue;
+                }
+            };
+            result.push_str(&escape(&value));
+            rest = &after[end + 2..];
+        }
+        result.push_str(rest);
+        result
+    }
 }
 
-#[derive(Copy, Clone, Debug, Default)]
-pub struct EmptyUpstream {}
+/// Result of a push which did not fail.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Pushed {
+    /// Upstream accepted the change.
+    Sent,
+    /// Upstream ignored the change, e.g. unchanged status or component not routed to it.
+    Skipped,
+}
 
 #[async_trait]
-impl UpstreamTrait for EmptyUpstream {
-    async fn get_component_status(&self, _component: &str, _page: &str) -> anyhow::Result<()> {
-        Ok(())
-    }
+pub trait UpstreamTrait: Send + Sync {
+    /// Unique name of upstream, used to track deliveries independently.
+    fn name(&self) -> &str;
 
-    async fn set_component_status(
+    /// Current status of a component on the upstream, only status pages keep one.
+    async fn get_component_status(
         &self,
         _component: &str,
         _page: &str,
-        _status: ComponentStatus,
-    ) -> anyhow::Result<()> {
-        Ok(())
+    ) -> anyhow::Result<ComponentStatus> {
+        Err(anyhow!(
+            "Upstream {} does not keep component status",
+            self.name()
+        ))
+    }
+
+    /// Push change to upstream, [`Pushed::Skipped`] if the change is nothing to this upstream.
+    async fn set_component_status(&self, change: &StatusChange) -> anyhow::Result<Pushed>;
+}
+
+pub type Upstreams = Arc<Vec<Box<dyn UpstreamTrait>>>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{component, TIMESTAMP};
+
+    fn change() -> StatusChange {
+        StatusChange::new(
+            &component("a", "Web \"1\""),
+            ServerLastStatus::Optional,
+            ServerLastStatus::Outage,
+            TIMESTAMP,
+        )
+    }
+
+    #[test]
+    fn test_render_placeholders() {
+        assert_eq!(
+            change().render(
+                "{{uuid}} {{ name }} {{page}} {{component_id}} {{old_status}} {{new_status}} {{timestamp}}",
+                |value| value.to_string()
+            ),
+            "a Web \"1\" p1 c1 operational major_outage 1600000000"
+        );
+    }
+
+    #[test]
+    fn test_render_keeps_unknown_and_unclosed() {
+        let change = change();
+        assert_eq!(
+            change.render("{{unknown}} {{uuid}}", |value| value.to_string()),
+            "{{unknown}} a"
+        );
+        assert_eq!(
+            change.render("{{uuid}} {{uuid", |value| value.to_string()),
+            "a {{uuid"
+        );
+        assert_eq!(change.render("}} {{", |value| value.to_string()), "}} {{");
+    }
+
+    #[test]
+    fn test_render_escape() {
+        assert_eq!(
+            change().render(r#"{"name": "{{name}}", "uuid": "{{uuid}}"}"#, |value| {
+                let quoted = serde_json::to_string(value).unwrap();
+                quoted[1..quoted.len() - 1].to_string()
+            }),
+            r#"{"name": "Web \"1\"", "uuid": "a"}"#
+        );
     }
 }
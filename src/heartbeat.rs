@@ -0,0 +1,134 @@
+/*
+ ** Copyright (C) 2022 KunoiSayami
+ **
+ ** This program is free software: you can redistribute it and/or modify
+ ** it under the terms of the GNU Affero General Public License as published by
+ ** the Free Software Foundation, either version 3 of the License, or
+ ** any later version.
+ **
+ ** This program is distributed in the hope that it will be useful,
+ ** but WITHOUT ANY WARRANTY; without even the implied warranty of
+ ** MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ ** GNU Affero General Public License for more details.
+ **
+ ** You should have received a copy of the GNU Affero General Public License
+ ** along with this program. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::configure::{Component, Configure};
+use crate::database::{get_current_timestamp, insert_history};
+use crate::datastructures::{ServerLastStatus, StatusChange, UpstreamTrait, Upstreams};
+use crate::metrics::Metrics;
+use crate::outbox::{lock_component, push_or_enqueue};
+use anyhow::anyhow;
+#[cfg(any(feature = "env_logger", feature = "log4rs"))]
+use log::{error, warn};
+#[cfg(feature = "spdlog-rs")]
+use spdlog::prelude::*;
+use sqlx::SqliteConnection;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+struct Watched {
+    component: Component,
+    timeout: u64,
+    status: ServerLastStatus,
+}
+
+/// Spawn the dead-man's switch, returns `None` if no component has `heartbeat_timeout`.
+pub fn spawn_heartbeat(
+    config: &Configure,
+    conn: Arc<Mutex<SqliteConnection>>,
+    upstreams: Upstreams,
+    metrics: Arc<Metrics>,
+) -> anyhow::Result<Option<JoinHandle<()>>> {
+    let mut watched = Vec::new();
+    for component in config.components() {
+        let timeout = match component.heartbeat_timeout() {
+            None => continue,
+            Some(timeout) => timeout,
+        };
+        let status = ServerLastStatus::try_from(component.heartbeat_status())?;
+        if status == ServerLastStatus::Unknown {
+            return Err(anyhow!(
+                "Invalid heartbeat_status {:?} of component {}",
+                component.heartbeat_status(),
+                component.uuid()
+            ));
+        }
+        watched.push(Watched {
+            component: component.clone(),
+            timeout,
+            status,
+        });
+    }
+    if watched.is_empty() {
+        return Ok(None);
+    }
+
+    Ok(Some(tokio::spawn(async move {
+        let mut interval = tokio::time::interval(CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            for watched in &watched {
+                check_component(watched, &conn, &upstreams, &metrics)
+                    .await
+                    .unwrap_or_else(|e| error!("Got error in heartbeat check: {:?}", e));
+            }
+        }
+    })))
+}
+
+async fn check_component(
+    watched: &Watched,
+    conn: &Mutex<SqliteConnection>,
+    upstreams: &[Box<dyn UpstreamTrait>],
+    metrics: &Metrics,
+) -> anyhow::Result<()> {
+    let uuid = watched.component.uuid();
+    let target = watched.status.to_string();
+    let guard = lock_component(uuid).await;
+    let previous = {
+        let mut conn = conn.lock().await;
+        let (status, last_update) = sqlx::query_as::<_, (String, i64)>(
+            r#"SELECT "status", "last_update" FROM "machines" WHERE "uuid" = ?"#,
+        )
+        .bind(uuid)
+        .fetch_one(&mut *conn)
+        .await
+        .map_err(|e| anyhow!("Fetch component {} error: {:?}", uuid, e))?;
+
+        if status == target
+            || get_current_timestamp() as i64 - last_update <= watched.timeout as i64
+        {
+            return Ok(());
+        }
+
+        // Keep last_update untouched, it still marks the last report from this component.
+        sqlx::query(r#"UPDATE "machines" SET "status" = ? WHERE "uuid" = ?"#)
+            .bind(&target)
+            .bind(uuid)
+            .execute(&mut *conn)
+            .await
+            .map_err(|e| anyhow!("Update component {} error: {:?}", uuid, e))?;
+        insert_history(&mut conn, uuid, &status, &target, "heartbeat").await?;
+        warn!(
+            "Component {} has no report in {} seconds, mark as {}",
+            uuid, watched.timeout, target
+        );
+        ServerLastStatus::try_from(&status)?
+    };
+
+    let change = StatusChange::new(
+        &watched.component,
+        previous,
+        watched.status,
+        get_current_timestamp(),
+    );
+    push_or_enqueue(conn, upstreams, metrics, &change, &guard).await?;
+    Ok(())
+}
@@ -19,9 +19,19 @@
 compile_error!("You should choose only one log feature");
 
 use crate::configure::Configure;
-use crate::database::get_current_timestamp;
-use crate::datastructures::{EmptyUpstream, UpstreamTrait};
+use crate::database::{get_current_timestamp, hash_token, init_database, spawn_prune_history};
+use crate::datastructures::UpstreamTrait;
+use crate::heartbeat::spawn_heartbeat;
+use crate::metrics::Metrics;
+use crate::outbox::spawn_outbox;
+use crate::reconcile::spawn_reconcile;
+use crate::resync::spawn_resync;
 use crate::statuspagelib::StatusPageUpstream;
+use crate::upstream::{
+    CachetUpstream, DiscordNotifier, EmailNotifier, GotifyUpstream, HookUpstream, InstatusUpstream,
+    MatrixNotifier, NtfyUpstream, PagerDutyUpstream, SlackNotifier, TelegramNotifier,
+    UptimeKumaUpstream, WebhookUpstream,
+};
 use crate::web_service::v1::make_router;
 use anyhow::anyhow;
 use clap::{arg, Command};
@@ -31,11 +41,22 @@ use log::{info, warn};
 use spdlog::{default_logger, init_log_crate_proxy, prelude::*, sink::FileSink};
 use sqlx::sqlite::SqliteConnectOptions;
 use sqlx::{ConnectOptions, SqliteConnection};
+use std::sync::Arc;
+use tokio::sync::Mutex;
 
 mod configure;
 mod database;
 mod datastructures;
+mod heartbeat;
+mod metrics;
+mod outbox;
+mod reconcile;
+mod resync;
 mod statuspagelib;
+#[cfg(test)]
+mod test_util;
+mod upstream;
+mod uptime;
 mod web_service;
 
 const DEFAULT_DATABASE_LOCATION: &str = "database.db";
@@ -44,6 +65,7 @@ async fn check_database(
     config: &Configure,
     mut conn: SqliteConnection,
 ) -> anyhow::Result<SqliteConnection> {
+    init_database(&mut conn).await?;
     for component in config.components() {
         let ret = sqlx::query_as::<_, (i32,)>(r#"SELECT 1 FROM "machines" WHERE "uuid" = ?"#)
             .bind(component.uuid())
@@ -57,49 +79,145 @@ async fn check_database(
                 )
             })?;
         if ret.is_none() {
-            sqlx::query(r#"INSERT INTO "machines" VALUES (?, 'unknown', ?, ?, ?, ?)"#)
-                .bind(component.uuid())
-                .bind(get_current_timestamp() as u32)
-                .bind(component.need_push())
-                .bind(if component.page().is_empty() {
-                    None
-                } else {
-                    Some(component.page().to_string())
-                })
-                .bind(if component.report_id().is_empty() {
-                    None
-                } else {
-                    Some(component.report_id().to_string())
-                })
-                .execute(&mut conn)
-                .await
-                .map_err(|e| {
-                    anyhow!(
-                        "Insert component error in check_database function {}: {:?}",
-                        component.uuid(),
-                        e
-                    )
-                })?;
+            sqlx::query(
+                r#"INSERT INTO "machines"
+                    ("uuid", "status", "last_update", "need_push", "page", "component_id")
+                    VALUES (?, 'unknown', ?, ?, ?, ?)"#,
+            )
+            .bind(component.uuid())
+            .bind(get_current_timestamp() as u32)
+            .bind(component.need_push())
+            .bind(if component.page().is_empty() {
+                None
+            } else {
+                Some(component.page().to_string())
+            })
+            .bind(if component.report_id().is_empty() {
+                None
+            } else {
+                Some(component.report_id().to_string())
+            })
+            .execute(&mut conn)
+            .await
+            .map_err(|e| {
+                anyhow!(
+                    "Insert component error in check_database function {}: {:?}",
+                    component.uuid(),
+                    e
+                )
+            })?;
             info!("Insert {} into database", component.uuid())
         }
+        // Configured token only seeds the database, rotated tokens in database take precedence.
+        if !component.token().is_empty() {
+            sqlx::query(
+                r#"UPDATE "machines" SET "token" = ? WHERE "uuid" = ? AND "token" IS NULL"#,
+            )
+            .bind(hash_token(component.token()))
+            .bind(component.uuid())
+            .execute(&mut conn)
+            .await
+            .map_err(|e| {
+                anyhow!(
+                    "Set component token error in check_database function {}: {:?}",
+                    component.uuid(),
+                    e
+                )
+            })?;
+        }
         // Current not check uuid not in database.
     }
     Ok(conn)
 }
 
+/// Every enabled upstream receives each status change.
+fn build_upstreams(
+    config: &Configure,
+    conn: &Arc<Mutex<SqliteConnection>>,
+) -> anyhow::Result<Vec<Box<dyn UpstreamTrait>>> {
+    let mut upstreams: Vec<Box<dyn UpstreamTrait>> = Vec::new();
+    if let Some(statuspage) = StatusPageUpstream::from_configure(config)? {
+        upstreams.push(Box::new(statuspage));
+    }
+    if let Some(cachet) = config.cachet() {
+        upstreams.push(Box::new(CachetUpstream::from_configure(cachet)?));
+    }
+    if let Some(instatus) = config.instatus() {
+        upstreams.push(Box::new(InstatusUpstream::from_configure(instatus)?));
+    }
+    if let Some(uptime_kuma) = config.uptime_kuma() {
+        upstreams.push(Box::new(UptimeKumaUpstream::from_configure(uptime_kuma)?));
+    }
+    for webhook in config.webhook() {
+        upstreams.push(Box::new(WebhookUpstream::from_configure(
+            webhook,
+            conn.clone(),
+        )?));
+    }
+    for slack in config.slack() {
+        upstreams.push(Box::new(SlackNotifier::from_configure(
+            slack,
+            conn.clone(),
+        )?));
+    }
+    for discord in config.discord() {
+        upstreams.push(Box::new(DiscordNotifier::from_configure(
+            discord,
+            conn.clone(),
+        )?));
+    }
+    if let Some(telegram) = config.telegram() {
+        upstreams.push(Box::new(TelegramNotifier::from_configure(
+            telegram,
+            conn.clone(),
+        )?));
+    }
+    if let Some(pagerduty) = config.pagerduty() {
+        upstreams.push(Box::new(PagerDutyUpstream::from_configure(pagerduty)?));
+    }
+    if let Some(email) = config.email() {
+        upstreams.push(Box::new(EmailNotifier::from_configure(
+            email,
+            conn.clone(),
+        )?));
+    }
+    if let Some(matrix) = config.matrix() {
+        upstreams.push(Box::new(MatrixNotifier::from_configure(
+            matrix,
+            conn.clone(),
+        )?));
+    }
+    if let Some(ntfy) = NtfyUpstream::from_configure(config, conn.clone())? {
+        upstreams.push(Box::new(ntfy));
+    }
+    if let Some(gotify) = GotifyUpstream::from_configure(config, conn.clone())? {
+        upstreams.push(Box::new(gotify));
+    }
+    for hook in config.hook() {
+        upstreams.push(Box::new(HookUpstream::from_configure(hook)));
+    }
+    for (index, upstream) in upstreams.iter().enumerate() {
+        if upstreams[..index]
+            .iter()
+            .any(|other| other.name() == upstream.name())
+        {
+            return Err(anyhow!("Duplicate upstream name {:?}", upstream.name()));
+        }
+    }
+    if upstreams.is_empty() {
+        warn!("No upstream enabled, status changes are only stored locally");
+    }
+    Ok(upstreams)
+}
+
 async fn async_main(config_file: &str) -> anyhow::Result<()> {
     let config = Configure::init_from_path(config_file)
         .await
         .map_err(|e| anyhow!("Read configure file failure: {:?}", e))?;
 
-    let upstream: Box<dyn UpstreamTrait> = if config.statuspage().enabled() {
-        Box::new(StatusPageUpstream::from_configure(&config)?.unwrap())
-    } else {
-        Box::new(EmptyUpstream::default())
-    };
-
     let sqlite_connection = SqliteConnectOptions::new()
         .filename(config.server().database_location())
+        .create_if_missing(true)
         .connect()
         .await
         .map_err(|e| {
@@ -110,7 +228,23 @@ async fn async_main(config_file: &str) -> anyhow::Result<()> {
             )
         })?;
 
-    let router = make_router(check_database(&config, sqlite_connection).await?, upstream);
+    if config.server().auth_header().is_empty() {
+        warn!("auth_header is empty, anyone can report component status");
+    }
+
+    let conn = Arc::new(Mutex::new(
+        check_database(&config, sqlite_connection).await?,
+    ));
+    let upstreams = Arc::new(build_upstreams(&config, &conn)?);
+    let metrics = Arc::new(Metrics::new()?);
+
+    let heartbeat = spawn_heartbeat(&config, conn.clone(), upstreams.clone(), metrics.clone())?;
+    let resync = spawn_resync(&config, conn.clone(), upstreams.clone(), metrics.clone());
+    let outbox = spawn_outbox(conn.clone(), upstreams.clone(), metrics.clone());
+    let reconcile = spawn_reconcile(&config, conn.clone(), upstreams.clone(), metrics.clone())?;
+    let prune_history = spawn_prune_history(&config, conn.clone());
+
+    let router = make_router(&config, conn, upstreams, metrics);
     let bind = format!("{}:{}", config.server().addr(), config.server().port());
     let server_handler = axum_server::Handle::new();
     let server = tokio::spawn(
@@ -132,6 +266,12 @@ async fn async_main(config_file: &str) -> anyhow::Result<()> {
         _ = server => {
         }
     }
+    for task in [heartbeat, resync, Some(outbox), reconcile, prune_history]
+        .into_iter()
+        .flatten()
+    {
+        task.abort();
+    }
     Ok(())
 }
 
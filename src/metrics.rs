@@ -0,0 +1,141 @@
+/*
+ ** Copyright (C) 2022 KunoiSayami
+ **
+ ** This program is free software: you can redistribute it and/or modify
+ ** it under the terms of the GNU Affero General Public License as published by
+ ** the Free Software Foundation, either version 3 of the License, or
+ ** any later version.
+ **
+ ** This program is distributed in the hope that it will be useful,
+ ** but WITHOUT ANY WARRANTY; without even the implied warranty of
+ ** MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ ** GNU Affero General Public License for more details.
+ **
+ ** You should have received a copy of the GNU Affero General Public License
+ ** along with this program. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::configure::Component;
+use crate::database::get_current_timestamp;
+use crate::datastructures::ServerLastStatus;
+use anyhow::anyhow;
+use prometheus::{
+    HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use sqlx::SqliteConnection;
+use std::time::Duration;
+
+const NAMESPACE: &str = "status_upstream";
+
+pub struct Metrics {
+    registry: Registry,
+    component_status: IntGaugeVec,
+    last_update_seconds: IntGaugeVec,
+    reports: IntCounterVec,
+    upstream_pushes: IntCounterVec,
+    http_requests: HistogramVec,
+}
+
+impl Metrics {
+    pub fn new() -> anyhow::Result<Self> {
+        let registry = Registry::new();
+        let component_status = IntGaugeVec::new(
+            Opts::new(
+                "component_status",
+                "Current status of component, 1 for the active status",
+            )
+            .namespace(NAMESPACE),
+            &["uuid", "name", "status"],
+        )?;
+        let last_update_seconds = IntGaugeVec::new(
+            Opts::new(
+                "component_last_update_seconds",
+                "Seconds since component last reported",
+            )
+            .namespace(NAMESPACE),
+            &["uuid", "name"],
+        )?;
+        let reports = IntCounterVec::new(
+            Opts::new("reports_total", "Status reports received").namespace(NAMESPACE),
+            &["uuid"],
+        )?;
+        let upstream_pushes = IntCounterVec::new(
+            Opts::new("upstream_pushes_total", "Status pushes to upstream").namespace(NAMESPACE),
+            &["upstream", "result"],
+        )?;
+        let http_requests = HistogramVec::new(
+            HistogramOpts::new("http_request_duration_seconds", "HTTP request latency")
+                .namespace(NAMESPACE),
+            &["status"],
+        )?;
+
+        registry.register(Box::new(component_status.clone()))?;
+        registry.register(Box::new(last_update_seconds.clone()))?;
+        registry.register(Box::new(reports.clone()))?;
+        registry.register(Box::new(upstream_pushes.clone()))?;
+        registry.register(Box::new(http_requests.clone()))?;
+
+        Ok(Self {
+            registry,
+            component_status,
+            last_update_seconds,
+            reports,
+            upstream_pushes,
+            http_requests,
+        })
+    }
+
+    pub fn observe_report(&self, uuid: &str) {
+        self.reports.with_label_values(&[uuid]).inc();
+    }
+
+    pub fn observe_upstream_push(&self, upstream: &str, success: bool) {
+        self.upstream_pushes
+            .with_label_values(&[upstream, if success { "success" } else { "failure" }])
+            .inc();
+    }
+
+    pub fn observe_request(&self, status: u16, latency: Duration) {
+        self.http_requests
+            .with_label_values(&[&status.to_string()])
+            .observe(latency.as_secs_f64());
+    }
+
+    /// Refresh component gauges from database and encode all metrics in text format.
+    pub async fn render(
+        &self,
+        conn: &mut SqliteConnection,
+        components: &[Component],
+    ) -> anyhow::Result<String> {
+        let rows = sqlx::query_as::<_, (String, String, i64)>(
+            r#"SELECT "uuid", "status", "last_update" FROM "machines""#,
+        )
+        .fetch_all(conn)
+        .await
+        .map_err(|e| anyhow!("Fetch components for metrics error: {:?}", e))?;
+
+        let now = get_current_timestamp() as i64;
+        self.component_status.reset();
+        self.last_update_seconds.reset();
+        for (uuid, status, last_update) in rows {
+            let name = components
+                .iter()
+                .find(|c| c.uuid() == uuid)
+                .map(|c| c.name())
+                .unwrap_or_default();
+            let current = ServerLastStatus::try_from(&status)?;
+            for variant in ServerLastStatus::VARIANTS {
+                self.component_status
+                    .with_label_values(&[&uuid, name, &variant.to_string()])
+                    .set((variant == current) as i64);
+            }
+            self.last_update_seconds
+                .with_label_values(&[&uuid, name])
+                .set(now - last_update);
+        }
+
+        TextEncoder::new()
+            .encode_to_string(&self.registry.gather())
+            .map_err(|e| anyhow!("Encode metrics error: {:?}", e))
+    }
+}
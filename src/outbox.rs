@@ -0,0 +1,363 @@
+/*
+ ** Copyright (C) 2022 KunoiSayami
+ **
+ ** This program is free software: you can redistribute it and/or modify
+ ** it under the terms of the GNU Affero General Public License as published by
+ ** the Free Software Foundation, either version 3 of the License, or
+ ** any later version.
+ **
+ ** This program is distributed in the hope that it will be useful,
+ ** but WITHOUT ANY WARRANTY; without even the implied warranty of
+ ** MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ ** GNU Affero General Public License for more details.
+ **
+ ** You should have received a copy of the GNU Affero General Public License
+ ** along with this program. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::configure::Component;
+use crate::database::get_current_timestamp;
+use crate::datastructures::{Pushed, ServerLastStatus, StatusChange, UpstreamTrait, Upstreams};
+use crate::metrics::Metrics;
+use anyhow::anyhow;
+use futures_util::future::join_all;
+#[cfg(any(feature = "env_logger", feature = "log4rs"))]
+use log::{error, info, warn};
+#[cfg(feature = "spdlog-rs")]
+use spdlog::prelude::*;
+use sqlx::SqliteConnection;
+use std::collections::HashMap;
+use std::sync::{Arc, LazyLock};
+use std::time::Duration;
+use tokio::sync::{Mutex, OwnedMutexGuard};
+use tokio::task::JoinHandle;
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(10);
+const BASE_BACKOFF: u64 = 30;
+const MAX_BACKOFF: u64 = 3600;
+
+/// Pushes of the same component are serialized, so a retry can not race a newer status.
+static COMPONENT_LOCKS: LazyLock<std::sync::Mutex<HashMap<String, Arc<Mutex<()>>>>> =
+    LazyLock::new(Default::default);
+
+/// Proof that the status of a component is locked, see [`lock_component`].
+pub struct ComponentGuard {
+    uuid: String,
+    _guard: OwnedMutexGuard<()>,
+}
+
+/// Lock status of a component, hold the guard from reading the previous status until
+/// [`push_or_enqueue`] returns so pushes follow the order status changed in.
+pub async fn lock_component(uuid: &str) -> ComponentGuard {
+    let lock = COMPONENT_LOCKS
+        .lock()
+        .unwrap()
+        .entry(uuid.to_string())
+        .or_default()
+        .clone();
+    ComponentGuard {
+        uuid: uuid.to_string(),
+        _guard: lock.lock_owned().await,
+    }
+}
+
+type OutboxRow = (
+    i64,
+    String,
+    String,
+    String,
+    String,
+    u32,
+    String,
+    String,
+    String,
+    i64,
+);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Delivery {
+    Delivered,
+    Queued,
+}
+
+fn backoff(attempts: u32) -> u64 {
+    BASE_BACKOFF
+        .saturating_mul(1 << attempts.min(16))
+        .min(MAX_BACKOFF)
+}
+
+/// Push status to every upstream concurrently, queue the push of each unreachable
+/// upstream in the outbox table independently.
+///
+/// Only the newest status of a component is kept in the outbox for each upstream,
+/// so a retried push never overwrites a status delivered later. An upstream skipping
+/// the change keeps its queued push, which is still undelivered.
+///
+/// `guard` must lock the component of `change`, see [`lock_component`].
+pub async fn push_or_enqueue(
+    conn: &Mutex<SqliteConnection>,
+    upstreams: &[Box<dyn UpstreamTrait>],
+    metrics: &Metrics,
+    change: &StatusChange,
+    guard: &ComponentGuard,
+) -> anyhow::Result<Delivery> {
+    debug_assert_eq!(guard.uuid, change.uuid());
+
+    let results = join_all(upstreams.iter().map(|upstream| async move {
+        let ret = upstream.set_component_status(change).await;
+        metrics.observe_upstream_push(upstream.name(), ret.is_ok());
+        (upstream.name(), ret)
+    }))
+    .await;
+
+    let mut conn = conn.lock().await;
+    let mut delivery = Delivery::Delivered;
+    for (name, ret) in results {
+        if let Ok(Pushed::Skipped) = ret {
+            continue;
+        }
+        sqlx::query(r#"DELETE FROM "outbox" WHERE "uuid" = ? AND "upstream" = ?"#)
+            .bind(change.uuid())
+            .bind(name)
+            .execute(&mut *conn)
+            .await
+            .map_err(|e| anyhow!("Clear outbox of {} error: {:?}", change.uuid(), e))?;
+
+        let e = match ret {
+            Ok(_) => continue,
+            Err(e) => e,
+        };
+        warn!(
+            "Push {} to upstream {} failed, queue to outbox: {:?}",
+            change.uuid(),
+            name,
+            e
+        );
+        sqlx::query(
+            r#"INSERT INTO "outbox"
+                ("uuid", "page", "component_id", "status", "attempts", "next_attempt",
+                "last_error", "created", "upstream", "name", "old_status")
+                VALUES (?, ?, ?, ?, 1, ?, ?, ?, ?, ?, ?)"#,
+        )
+        .bind(change.uuid())
+        .bind(change.page())
+        .bind(change.component_id())
+        .bind(change.new_status().to_string())
+        .bind(get_current_timestamp() as i64 + backoff(0) as i64)
+        .bind(format!("{:?}", e))
+        .bind(change.timestamp() as i64)
+        .bind(name)
+        .bind(change.name())
+        .bind(change.old_status().to_string())
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| anyhow!("Queue {} to outbox error: {:?}", change.uuid(), e))?;
+        delivery = Delivery::Queued;
+    }
+    Ok(delivery)
+}
+
+/// Spawn the worker retrying queued pushes with exponential backoff.
+pub fn spawn_outbox(
+    conn: Arc<Mutex<SqliteConnection>>,
+    upstreams: Upstreams,
+    metrics: Arc<Metrics>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            retry_due(&conn, &upstreams, &metrics)
+                .await
+                .unwrap_or_else(|e| error!("Got error while retry outbox: {:?}", e));
+        }
+    })
+}
+
+async fn retry_due(
+    conn: &Mutex<SqliteConnection>,
+    upstreams: &[Box<dyn UpstreamTrait>],
+    metrics: &Metrics,
+) -> anyhow::Result<()> {
+    let rows = {
+        let mut conn = conn.lock().await;
+        sqlx::query_as::<_, OutboxRow>(
+            r#"SELECT "id", "uuid", "page", "component_id", "status", "attempts", "upstream",
+                "name", "old_status", "created"
+                FROM "outbox" WHERE "next_attempt" <= ? ORDER BY "id""#,
+        )
+        .bind(get_current_timestamp() as i64)
+        .fetch_all(&mut *conn)
+        .await
+        .map_err(|e| anyhow!("Fetch outbox error: {:?}", e))?
+    };
+
+    for (
+        id,
+        uuid,
+        page,
+        component_id,
+        status,
+        attempts,
+        name,
+        component_name,
+        old_status,
+        created,
+    ) in rows
+    {
+        let upstream = match upstreams.iter().find(|upstream| upstream.name() == name) {
+            Some(upstream) => upstream,
+            None => {
+                warn!(
+                    "Drop queued status of {} for removed upstream {}",
+                    uuid, name
+                );
+                delete_queued(conn, id).await?;
+                continue;
+            }
+        };
+        let _guard = lock_component(&uuid).await;
+        // The row is replaced or cleared if a newer status was pushed since it was fetched.
+        if !is_queued(conn, id).await? {
+            continue;
+        }
+        let change = StatusChange::new(
+            &Component::new(
+                uuid.clone(),
+                component_name,
+                component_id,
+                page,
+                String::new(),
+            ),
+            ServerLastStatus::try_from(&old_status)?,
+            ServerLastStatus::try_from(&status)?,
+            created as u64,
+        );
+        let ret = upstream.set_component_status(&change).await;
+        metrics.observe_upstream_push(&name, ret.is_ok());
+
+        match ret {
+            // Upstream has nothing to send for the queued change anymore.
+            Ok(Pushed::Skipped) => delete_queued(conn, id).await?,
+            Ok(Pushed::Sent) => {
+                delete_queued(conn, id).await?;
+                info!(
+                    "Deliver queued status {} of {} to {} after {} attempt(s)",
+                    status,
+                    uuid,
+                    name,
+                    attempts + 1
+                );
+            }
+            Err(e) => {
+                let mut conn = conn.lock().await;
+                sqlx::query(
+                    r#"UPDATE "outbox" SET "attempts" = ?, "next_attempt" = ?, "last_error" = ?
+                        WHERE "id" = ?"#,
+                )
+                .bind(attempts + 1)
+                .bind(get_current_timestamp() as i64 + backoff(attempts) as i64)
+                .bind(format!("{:?}", e))
+                .bind(id)
+                .execute(&mut *conn)
+                .await
+                .map_err(|e| anyhow!("Update outbox {} error: {:?}", id, e))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn is_queued(conn: &Mutex<SqliteConnection>, id: i64) -> anyhow::Result<bool> {
+    let mut conn = conn.lock().await;
+    let ret = sqlx::query_as::<_, (i32,)>(r#"SELECT 1 FROM "outbox" WHERE "id" = ?"#)
+        .bind(id)
+        .fetch_optional(&mut *conn)
+        .await
+        .map_err(|e| anyhow!("Fetch outbox {} error: {:?}", id, e))?;
+    Ok(ret.is_some())
+}
+
+async fn delete_queued(conn: &Mutex<SqliteConnection>, id: i64) -> anyhow::Result<()> {
+    let mut conn = conn.lock().await;
+    sqlx::query(r#"DELETE FROM "outbox" WHERE "id" = ?"#)
+        .bind(id)
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| anyhow!("Delete outbox {} error: {:?}", id, e))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{change, memory_database};
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    /// Skips unchanged status like event upstreams, fails while `down` is set.
+    #[derive(Default)]
+    struct EventUpstream {
+        down: AtomicBool,
+    }
+
+    #[async_trait::async_trait]
+    impl UpstreamTrait for EventUpstream {
+        fn name(&self) -> &str {
+            "event"
+        }
+
+        async fn set_component_status(&self, change: &StatusChange) -> anyhow::Result<Pushed> {
+            if change.old_status() == change.new_status() {
+                return Ok(Pushed::Skipped);
+            }
+            if self.down.load(Ordering::SeqCst) {
+                return Err(anyhow!("upstream is down"));
+            }
+            Ok(Pushed::Sent)
+        }
+    }
+
+    async fn queued(conn: &Mutex<SqliteConnection>) -> Vec<(String, String)> {
+        sqlx::query_as(r#"SELECT "upstream", "status" FROM "outbox""#)
+            .fetch_all(&mut *conn.lock().await)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_skip_keeps_queued_push() {
+        let conn = Mutex::new(memory_database().await);
+        let upstream = EventUpstream::default();
+        upstream.down.store(true, Ordering::SeqCst);
+        let upstreams: Vec<Box<dyn UpstreamTrait>> = vec![Box::new(upstream)];
+        let metrics = Metrics::new().unwrap();
+        let outage = change(ServerLastStatus::Optional, ServerLastStatus::Outage);
+        let guard = lock_component(outage.uuid()).await;
+
+        assert_eq!(
+            push_or_enqueue(&conn, &upstreams, &metrics, &outage, &guard)
+                .await
+                .unwrap(),
+            Delivery::Queued
+        );
+        let expected = vec![("event".to_string(), "major_outage".to_string())];
+        assert_eq!(queued(&conn).await, expected);
+
+        // Resync repeats the status, which the upstream skips, the queued push must survive.
+        let resync = change(ServerLastStatus::Outage, ServerLastStatus::Outage);
+        push_or_enqueue(&conn, &upstreams, &metrics, &resync, &guard)
+            .await
+            .unwrap();
+        assert_eq!(queued(&conn).await, expected);
+
+        let recovered = change(ServerLastStatus::Outage, ServerLastStatus::Optional);
+        let upstreams: Vec<Box<dyn UpstreamTrait>> = vec![Box::new(EventUpstream::default())];
+        assert_eq!(
+            push_or_enqueue(&conn, &upstreams, &metrics, &recovered, &guard)
+                .await
+                .unwrap(),
+            Delivery::Delivered
+        );
+        assert!(queued(&conn).await.is_empty());
+    }
+}
@@ -0,0 +1,300 @@
+/*
+ ** Copyright (C) 2022 KunoiSayami
+ **
+ ** This program is free software: you can redistribute it and/or modify
+ ** it under the terms of the GNU Affero General Public License as published by
+ ** the Free Software Foundation, either version 3 of the License, or
+ ** any later version.
+ **
+ ** This program is distributed in the hope that it will be useful,
+ ** but WITHOUT ANY WARRANTY; without even the implied warranty of
+ ** MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ ** GNU Affero General Public License for more details.
+ **
+ ** You should have received a copy of the GNU Affero General Public License
+ ** along with this program. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::configure::{Authority, Component, Configure};
+use crate::database::{get_current_timestamp, insert_history};
+use crate::datastructures::{ServerLastStatus, StatusChange, UpstreamTrait, Upstreams};
+use crate::metrics::Metrics;
+use crate::outbox::{lock_component, push_or_enqueue};
+use crate::statuspagelib::{ComponentStatus, StatusPageUpstream};
+use crate::web_service::current::FetchWithStatusReturnType;
+use anyhow::anyhow;
+#[cfg(any(feature = "env_logger", feature = "log4rs"))]
+use log::{error, info, warn};
+#[cfg(feature = "spdlog-rs")]
+use spdlog::prelude::*;
+use sqlx::SqliteConnection;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+
+/// Spawn reconciliation of component status between database and statuspage.io, runs once
+/// immediately and then every `reconcile_interval` if set.
+///
+/// Returns `None` if statuspage.io is disabled or no component is configured.
+pub fn spawn_reconcile(
+    config: &Configure,
+    conn: Arc<Mutex<SqliteConnection>>,
+    upstreams: Upstreams,
+    metrics: Arc<Metrics>,
+) -> anyhow::Result<Option<JoinHandle<()>>> {
+    let statuspage = match StatusPageUpstream::from_configure(config)? {
+        Some(statuspage) => statuspage,
+        None => return Ok(None),
+    };
+    if config.is_empty_services() {
+        return Ok(None);
+    }
+    let authority = config.statuspage().authority();
+    let period = config
+        .statuspage()
+        .reconcile_interval()
+        .map(Duration::from_secs);
+    let components = config.components().clone();
+    Ok(Some(tokio::spawn(async move {
+        loop {
+            reconcile(
+                &conn,
+                &statuspage,
+                &upstreams,
+                &metrics,
+                &components,
+                authority,
+            )
+            .await
+            .unwrap_or_else(|e| error!("Got error while reconcile statuspage: {:?}", e));
+            match period {
+                Some(period) => tokio::time::sleep(period).await,
+                None => break,
+            }
+        }
+    })))
+}
+
+async fn reconcile(
+    conn: &Mutex<SqliteConnection>,
+    statuspage: &StatusPageUpstream,
+    upstreams: &[Box<dyn UpstreamTrait>],
+    metrics: &Metrics,
+    components: &[Component],
+    authority: Authority,
+) -> anyhow::Result<()> {
+    let rows = {
+        let mut conn = conn.lock().await;
+        sqlx::query_as::<_, FetchWithStatusReturnType>(
+            r#"SELECT "uuid", "page", "component_id", "token", "status" FROM "machines""#,
+        )
+        .fetch_all(&mut *conn)
+        .await
+        .map_err(|e| anyhow!("Fetch components for reconcile error: {:?}", e))?
+    };
+
+    let mut corrected = 0;
+    for (uuid, page, component_id, token, status) in rows {
+        let component = match components.iter().find(|c| c.uuid() == uuid) {
+            Some(component) => component.clone(),
+            None => Component::from((uuid, page, component_id, token)),
+        };
+        // Component is not bound to any statuspage.io component.
+        if component.report_id().is_empty() || component.page().is_empty() {
+            continue;
+        }
+        let local = ServerLastStatus::try_from(&status)?;
+        let remote = match statuspage
+            .get_component_status(component.report_id(), component.page())
+            .await
+        {
+            Ok(remote) => remote,
+            Err(e) => {
+                error!(
+                    "Fetch statuspage status of {} error: {:?}",
+                    component.uuid(),
+                    e
+                );
+                continue;
+            }
+        };
+        if remote.server_status() == Some(local) {
+            continue;
+        }
+
+        let ret = match authority {
+            Authority::Local => {
+                correct_upstream(conn, statuspage, metrics, &component, local, remote).await
+            }
+            Authority::Upstream => {
+                correct_local(conn, upstreams, metrics, &component, local, remote).await
+            }
+        };
+        match ret {
+            Ok(true) => corrected += 1,
+            Ok(false) => {}
+            Err(e) => error!("Reconcile component {} error: {:?}", component.uuid(), e),
+        }
+    }
+    info!("Reconcile corrected {} component(s)", corrected);
+    Ok(())
+}
+
+async fn correct_upstream(
+    conn: &Mutex<SqliteConnection>,
+    statuspage: &StatusPageUpstream,
+    metrics: &Metrics,
+    component: &Component,
+    local: ServerLastStatus,
+    remote: ComponentStatus,
+) -> anyhow::Result<bool> {
+    // Nothing reported yet, statuspage.io is the only source.
+    if local == ServerLastStatus::Unknown {
+        return Ok(false);
+    }
+    // Maintenance is set by hand on statuspage.io, reports do not end it.
+    if remote == ComponentStatus::UnderMaintenance {
+        info!(
+            "Component {} is {} on statuspage, keep it instead of {}",
+            component.uuid(),
+            remote,
+            local
+        );
+        return Ok(false);
+    }
+    let _guard = lock_component(component.uuid()).await;
+    let (status,) = {
+        let mut conn = conn.lock().await;
+        sqlx::query_as::<_, (String,)>(r#"SELECT "status" FROM "machines" WHERE "uuid" = ?"#)
+            .bind(component.uuid())
+            .fetch_one(&mut *conn)
+            .await
+            .map_err(|e| anyhow!("Fetch component {} error: {:?}", component.uuid(), e))?
+    };
+    // Skip if a report arrived while statuspage was queried, its own push corrects statuspage.
+    if status != local.to_string() {
+        return Ok(false);
+    }
+    warn!(
+        "Component {} is {} on statuspage, correct to {}",
+        component.uuid(),
+        remote,
+        local
+    );
+    let change = StatusChange::new(component, local, local, get_current_timestamp());
+    let ret = statuspage.set_component_status(&change).await;
+    metrics.observe_upstream_push(statuspage.name(), ret.is_ok());
+    ret.map(|_| true)
+}
+
+async fn correct_local(
+    conn: &Mutex<SqliteConnection>,
+    upstreams: &[Box<dyn UpstreamTrait>],
+    metrics: &Metrics,
+    component: &Component,
+    local: ServerLastStatus,
+    remote: ComponentStatus,
+) -> anyhow::Result<bool> {
+    let remote_status = match remote.server_status() {
+        Some(status) => status,
+        None => {
+            info!(
+                "Component {} is {} on statuspage, keep local status {}",
+                component.uuid(),
+                remote,
+                local
+            );
+            return Ok(false);
+        }
+    };
+    let guard = lock_component(component.uuid()).await;
+    {
+        let mut conn = conn.lock().await;
+        // Skip if a report arrived while statuspage was queried.
+        let ret =
+            sqlx::query(r#"UPDATE "machines" SET "status" = ? WHERE "uuid" = ? AND "status" = ?"#)
+                .bind(remote_status.to_string())
+                .bind(component.uuid())
+                .bind(local.to_string())
+                .execute(&mut *conn)
+                .await
+                .map_err(|e| anyhow!("Update component {} error: {:?}", component.uuid(), e))?;
+        if ret.rows_affected() == 0 {
+            return Ok(false);
+        }
+        insert_history(
+            &mut conn,
+            component.uuid(),
+            &local.to_string(),
+            &remote_status.to_string(),
+            "reconcile",
+        )
+        .await?;
+    }
+    warn!(
+        "Component {} is {} locally, correct to {} from statuspage",
+        component.uuid(),
+        local,
+        remote_status
+    );
+    let change = StatusChange::new(component, local, remote_status, get_current_timestamp());
+    push_or_enqueue(conn, upstreams, metrics, &change, &guard).await?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{component, configure, memory_database, mock_ok, received};
+
+    #[tokio::test]
+    async fn test_correct_upstream_rechecks_status() {
+        let (url, log) = mock_ok();
+        let cfg = configure(&format!(
+            "[statuspage]\nenabled = true\noauth = \"OAuth key\"\nurl = \"{}\"\n",
+            url
+        ));
+        let statuspage = StatusPageUpstream::from_configure(&cfg).unwrap().unwrap();
+        let metrics = Metrics::new().unwrap();
+        let conn = Mutex::new(memory_database().await);
+        sqlx::query(
+            r#"INSERT INTO "machines" ("uuid", "status", "last_update", "need_push", "page", "component_id")
+                VALUES ('a', 'major_outage', 0, 1, 'p1', 'c1')"#,
+        )
+        .execute(&mut *conn.lock().await)
+        .await
+        .unwrap();
+        let component = component("a", "Web");
+
+        // A report changed the status after reconcile read it as operational.
+        assert!(!correct_upstream(
+            &conn,
+            &statuspage,
+            &metrics,
+            &component,
+            ServerLastStatus::Optional,
+            ComponentStatus::PartialOutage,
+        )
+        .await
+        .unwrap());
+        assert!(received(&log).is_empty());
+
+        assert!(correct_upstream(
+            &conn,
+            &statuspage,
+            &metrics,
+            &component,
+            ServerLastStatus::Outage,
+            ComponentStatus::PartialOutage,
+        )
+        .await
+        .unwrap());
+        let received = received(&log);
+        assert_eq!(received.len(), 1);
+        assert_eq!(
+            received[0].json(),
+            serde_json::json!({ "component": { "status": "major_outage" } })
+        );
+    }
+}
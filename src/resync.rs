@@ -0,0 +1,100 @@
+/*
+ ** Copyright (C) 2022 KunoiSayami
+ **
+ ** This program is free software: you can redistribute it and/or modify
+ ** it under the terms of the GNU Affero General Public License as published by
+ ** the Free Software Foundation, either version 3 of the License, or
+ ** any later version.
+ **
+ ** This program is distributed in the hope that it will be useful,
+ ** but WITHOUT ANY WARRANTY; without even the implied warranty of
+ ** MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ ** GNU Affero General Public License for more details.
+ **
+ ** You should have received a copy of the GNU Affero General Public License
+ ** along with this program. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::configure::{Component, Configure};
+use crate::database::get_current_timestamp;
+use crate::datastructures::{ServerLastStatus, StatusChange, UpstreamTrait, Upstreams};
+use crate::metrics::Metrics;
+use crate::outbox::{lock_component, push_or_enqueue, Delivery};
+use crate::web_service::current::FetchWithStatusReturnType;
+use anyhow::anyhow;
+#[cfg(any(feature = "env_logger", feature = "log4rs"))]
+use log::{error, info};
+#[cfg(feature = "spdlog-rs")]
+use spdlog::prelude::*;
+use sqlx::SqliteConnection;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+
+/// Spawn periodic resync of all component statuses, returns `None` if `resync_interval` is unset.
+pub fn spawn_resync(
+    config: &Configure,
+    conn: Arc<Mutex<SqliteConnection>>,
+    upstreams: Upstreams,
+    metrics: Arc<Metrics>,
+) -> Option<JoinHandle<()>> {
+    let period = Duration::from_secs(config.server().resync_interval()?);
+    let components = config.components().clone();
+    Some(tokio::spawn(async move {
+        let mut interval = tokio::time::interval_at(tokio::time::Instant::now() + period, period);
+        loop {
+            interval.tick().await;
+            resync(&conn, &upstreams, &metrics, &components)
+                .await
+                .unwrap_or_else(|e| error!("Got error while resync upstream: {:?}", e));
+        }
+    }))
+}
+
+async fn resync(
+    conn: &Mutex<SqliteConnection>,
+    upstreams: &[Box<dyn UpstreamTrait>],
+    metrics: &Metrics,
+    components: &[Component],
+) -> anyhow::Result<()> {
+    let rows = {
+        let mut conn = conn.lock().await;
+        sqlx::query_as::<_, FetchWithStatusReturnType>(
+            r#"SELECT "uuid", "page", "component_id", "token", "status" FROM "machines""#,
+        )
+        .fetch_all(&mut *conn)
+        .await
+        .map_err(|e| anyhow!("Fetch components for resync error: {:?}", e))?
+    };
+
+    let mut pushed = 0;
+    for (uuid, page, component_id, token, _) in rows {
+        let component = match components.iter().find(|c| c.uuid() == uuid) {
+            Some(component) => component.clone(),
+            None => Component::from((uuid, page, component_id, token)),
+        };
+        // Read status again under the lock, a report may have changed it since.
+        let guard = lock_component(component.uuid()).await;
+        let (status,) = {
+            let mut conn = conn.lock().await;
+            sqlx::query_as::<_, (String,)>(r#"SELECT "status" FROM "machines" WHERE "uuid" = ?"#)
+                .bind(component.uuid())
+                .fetch_one(&mut *conn)
+                .await
+                .map_err(|e| anyhow!("Fetch component {} error: {:?}", component.uuid(), e))?
+        };
+        let status = ServerLastStatus::try_from(&status)?;
+        if status == ServerLastStatus::Unknown {
+            continue;
+        }
+        let change = StatusChange::new(&component, status, status, get_current_timestamp());
+        match push_or_enqueue(conn, upstreams, metrics, &change, &guard).await {
+            Ok(Delivery::Delivered) => pushed += 1,
+            Ok(Delivery::Queued) => {}
+            Err(e) => error!("Resync component {} error: {:?}", component.uuid(), e),
+        }
+    }
+    info!("Resync {} component(s) to upstream", pushed);
+    Ok(())
+}
@@ -15,20 +15,18 @@
  ** along with this program. If not, see <https://www.gnu.org/licenses/>.
  */
 
-const UPSTREAM_URL: &str = "https://api.statuspage.io/";
-
 mod v1 {
-    use super::UPSTREAM_URL;
-    use crate::datastructures::{ServerLastStatus, UpstreamTrait};
+    use crate::datastructures::{Pushed, ServerLastStatus, StatusChange, UpstreamTrait};
     use crate::Configure;
     use anyhow::anyhow;
     use reqwest::header::{HeaderMap, HeaderValue};
     use reqwest::Client;
+    use serde::Deserialize;
     use serde_json::json;
     use std::fmt::Formatter;
     use std::time::Duration;
 
-    #[allow(dead_code)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
     pub enum ComponentStatus {
         Operational,
         UnderMaintenance,
@@ -52,6 +50,19 @@ mod v1 {
         }
     }
 
+    impl ComponentStatus {
+        /// `None` for `under_maintenance`, which components can not report.
+        pub fn server_status(&self) -> Option<ServerLastStatus> {
+            match self {
+                ComponentStatus::Operational => Some(ServerLastStatus::Optional),
+                ComponentStatus::UnderMaintenance => None,
+                ComponentStatus::DegradedPerformance => Some(ServerLastStatus::DegradedPerformance),
+                ComponentStatus::PartialOutage => Some(ServerLastStatus::PartialOutage),
+                ComponentStatus::MajorOutage => Some(ServerLastStatus::Outage),
+            }
+        }
+    }
+
     impl From<bool> for ComponentStatus {
         fn from(b: bool) -> Self {
             if b {
@@ -78,26 +89,39 @@ mod v1 {
         }
     }
 
-    impl From<&ServerLastStatus> for ComponentStatus {
-        fn from(status: &ServerLastStatus) -> Self {
-            match status {
+    /// Status pages have no counterpart of `unknown`, nothing is reported yet.
+    impl TryFrom<&ServerLastStatus> for ComponentStatus {
+        type Error = anyhow::Error;
+
+        fn try_from(status: &ServerLastStatus) -> Result<Self, Self::Error> {
+            Ok(match status {
                 ServerLastStatus::Optional => ComponentStatus::Operational,
                 ServerLastStatus::Outage => ComponentStatus::MajorOutage,
                 ServerLastStatus::DegradedPerformance => ComponentStatus::DegradedPerformance,
                 ServerLastStatus::PartialOutage => ComponentStatus::PartialOutage,
-                ServerLastStatus::Unknown => unreachable!(),
-            }
+                ServerLastStatus::Unknown => {
+                    return Err(anyhow!("unknown status has no status page counterpart"))
+                }
+            })
         }
     }
 
-    impl From<ServerLastStatus> for ComponentStatus {
-        fn from(status: ServerLastStatus) -> Self {
-            Self::from(&status)
+    impl TryFrom<ServerLastStatus> for ComponentStatus {
+        type Error = anyhow::Error;
+
+        fn try_from(status: ServerLastStatus) -> Result<Self, Self::Error> {
+            Self::try_from(&status)
         }
     }
 
+    #[derive(Deserialize)]
+    struct ComponentResponse {
+        status: String,
+    }
+
     #[derive(Debug, Clone)]
     pub struct StatusPageUpstream {
+        base_url: String,
         client: Client,
     }
 
@@ -116,6 +140,7 @@ mod v1 {
                     .expect("OAuth Header value parse error"),
             );
             Ok(Some(Self {
+                base_url: format!("{}/", cfg.statuspage().url().trim_end_matches('/')),
                 client: reqwest::ClientBuilder::new()
                     .default_headers(map.clone())
                     .timeout(Duration::from_secs(10))
@@ -127,7 +152,7 @@ mod v1 {
         pub fn build_request_url(&self, component_id: &str, page: &str) -> String {
             format!(
                 "{basic_url}v1/pages/{page_id}/components/{component_id}",
-                basic_url = UPSTREAM_URL,
+                basic_url = self.base_url,
                 page_id = page,
                 component_id = component_id
             )
@@ -136,34 +161,150 @@ mod v1 {
 
     #[async_trait::async_trait]
     impl UpstreamTrait for StatusPageUpstream {
-        async fn get_component_status(&self, component: &str, page: &str) -> anyhow::Result<()> {
-            self.client
-                .get(self.build_request_url(component, page))
-                .send()
-                .await?;
-            Ok(())
+        fn name(&self) -> &str {
+            "statuspage"
         }
 
-        async fn set_component_status(
+        async fn get_component_status(
             &self,
             component: &str,
             page: &str,
-            status: ComponentStatus,
-        ) -> anyhow::Result<()> {
+        ) -> anyhow::Result<ComponentStatus> {
+            let ret = self
+                .client
+                .get(self.build_request_url(component, page))
+                .send()
+                .await?
+                .error_for_status()?
+                .json::<ComponentResponse>()
+                .await?;
+            ComponentStatus::try_from(ret.status.as_str())
+        }
+
+        async fn set_component_status(&self, change: &StatusChange) -> anyhow::Result<Pushed> {
+            // Component is not bound to any statuspage.io component.
+            if change.component_id().is_empty() || change.page().is_empty() {
+                return Ok(Pushed::Skipped);
+            }
+            // Nothing is known about the component yet, leave statuspage.io as is.
+            let status = match change.status() {
+                Some(status) => status,
+                None => return Ok(Pushed::Skipped),
+            };
             let payload = json!({
                 "component": {
                     "status": status.to_string()
                 }
             });
             self.client
-                .patch(self.build_request_url(component, page))
+                .patch(self.build_request_url(change.component_id(), change.page()))
                 .json(&payload)
                 .send()
-                .await?;
-            Ok(())
+                .await?
+                .error_for_status()?;
+            Ok(Pushed::Sent)
         }
     }
 }
 
 pub use v1::ComponentStatus;
 pub use v1::StatusPageUpstream;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datastructures::{ServerLastStatus, UpstreamTrait};
+    use crate::test_util::{change, configure, mock_server, received};
+    use axum::http::{Method, StatusCode};
+    use serde_json::json;
+
+    fn statuspage(url: &str) -> StatusPageUpstream {
+        let cfg = configure(&format!(
+            "[statuspage]\nenabled = true\noauth = \"OAuth key\"\nurl = \"{}\"\n",
+            url
+        ));
+        StatusPageUpstream::from_configure(&cfg).unwrap().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_get_component_status() {
+        let (url, log) = mock_server(|_| {
+            (
+                StatusCode::OK,
+                json!({ "id": "c1", "status": "under_maintenance" }).to_string(),
+            )
+        });
+        // Base url works with or without the trailing slash.
+        for url in [format!("{}/api/", url), format!("{}/api", url)] {
+            assert_eq!(
+                statuspage(&url)
+                    .get_component_status("c1", "p1")
+                    .await
+                    .unwrap(),
+                ComponentStatus::UnderMaintenance
+            );
+        }
+        assert_eq!(received(&log).len(), 2);
+        for received in received(&log) {
+            assert_eq!(received.method, Method::GET);
+            assert_eq!(received.uri.path(), "/api/v1/pages/p1/components/c1");
+            assert_eq!(received.header("authorization"), "OAuth key");
+        }
+
+        let (url, _) = mock_server(|_| {
+            (
+                StatusCode::OK,
+                json!({ "id": "c1", "status": "bogus" }).to_string(),
+            )
+        });
+        assert!(statuspage(&url)
+            .get_component_status("c1", "p1")
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_set_component_status() {
+        let (url, log) = mock_server(|_| (StatusCode::OK, "{}".to_string()));
+        let statuspage = statuspage(&url);
+
+        statuspage
+            .set_component_status(&change(
+                ServerLastStatus::Optional,
+                ServerLastStatus::PartialOutage,
+            ))
+            .await
+            .unwrap();
+        // Unknown status has no counterpart on statuspage.io and is skipped.
+        statuspage
+            .set_component_status(&change(
+                ServerLastStatus::Optional,
+                ServerLastStatus::Unknown,
+            ))
+            .await
+            .unwrap();
+
+        let received = received(&log);
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].method, Method::PATCH);
+        assert_eq!(received[0].uri.path(), "/v1/pages/p1/components/c1");
+        assert_eq!(received[0].header("authorization"), "OAuth key");
+        assert_eq!(
+            received[0].json(),
+            json!({ "component": { "status": "partial_outage" } })
+        );
+    }
+
+    #[test]
+    fn test_unknown_status() {
+        assert!(ComponentStatus::try_from(ServerLastStatus::Unknown).is_err());
+        assert_eq!(
+            ComponentStatus::try_from(ServerLastStatus::Outage).unwrap(),
+            ComponentStatus::MajorOutage
+        );
+        assert_eq!(
+            change(ServerLastStatus::Optional, ServerLastStatus::Unknown).status(),
+            None
+        );
+    }
+}
@@ -0,0 +1,136 @@
+/*
+ ** Copyright (C) 2022 KunoiSayami
+ **
+ ** This program is free software: you can redistribute it and/or modify
+ ** it under the terms of the GNU Affero General Public License as published by
+ ** the Free Software Foundation, either version 3 of the License, or
+ ** any later version.
+ **
+ ** This program is distributed in the hope that it will be useful,
+ ** but WITHOUT ANY WARRANTY; without even the implied warranty of
+ ** MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ ** GNU Affero General Public License for more details.
+ **
+ ** You should have received a copy of the GNU Affero General Public License
+ ** along with this program. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Fixtures shared by unit tests.
+
+use crate::configure::{Component, Configure};
+use crate::database::init_database;
+use crate::datastructures::{ServerLastStatus, StatusChange};
+use axum::http::{HeaderMap, Method, StatusCode, Uri};
+use axum::Router;
+use sqlx::{Connection, SqliteConnection};
+use std::sync::{Arc, Mutex};
+
+/// Timestamp of every change built by [`change`], 2020-09-13T12:26:40Z.
+pub const TIMESTAMP: u64 = 1600000000;
+
+/// Component `uuid` bound to statuspage component `c1` of page `p1`.
+pub fn component(uuid: &str, name: &str) -> Component {
+    Component::new(
+        uuid.to_string(),
+        name.to_string(),
+        "c1".to_string(),
+        "p1".to_string(),
+        String::new(),
+    )
+}
+
+/// Change of component `a` named `Web` at [`TIMESTAMP`].
+pub fn change(old_status: ServerLastStatus, new_status: ServerLastStatus) -> StatusChange {
+    StatusChange::new(&component("a", "Web"), old_status, new_status, TIMESTAMP)
+}
+
+/// Parse `context` as configure, a disabled `[statuspage]`, an open `[server]` and an empty
+/// component list are added unless `context` has its own.
+pub fn configure(context: &str) -> Configure {
+    let mut context = context.to_string();
+    if !context.contains("[statuspage]") {
+        context.push_str("\n[statuspage]\nenabled = false\n");
+    }
+    if !context.contains("[server]") {
+        context.push_str(
+            "\n[server]\naddr = \"127.0.0.1\"\nport = 41132\npublic_status_page = false\n",
+        );
+    }
+    if !context.contains("[[components]]") {
+        context.insert_str(0, "components = []\n");
+    }
+    toml::from_str(&context).unwrap()
+}
+
+/// In-memory database with the current schema.
+pub async fn memory_database() -> SqliteConnection {
+    let mut conn = SqliteConnection::connect("sqlite::memory:").await.unwrap();
+    init_database(&mut conn).await.unwrap();
+    conn
+}
+
+/// Request seen by [`mock_server`].
+#[derive(Clone, Debug)]
+pub struct Received {
+    pub method: Method,
+    pub uri: Uri,
+    pub headers: HeaderMap,
+    pub body: String,
+}
+
+impl Received {
+    pub fn json(&self) -> serde_json::Value {
+        serde_json::from_str(&self.body).unwrap()
+    }
+
+    pub fn header(&self, name: &str) -> &str {
+        self.headers[name].to_str().unwrap()
+    }
+}
+
+pub type ReceivedLog = Arc<Mutex<Vec<Received>>>;
+
+/// Serve `router` on a random local port, returns `http://127.0.0.1:<port>`.
+pub fn serve(router: Router) -> String {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(
+        axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(router.into_make_service()),
+    );
+    format!("http://{}", addr)
+}
+
+/// Record every request and answer it with `respond`, returns the base url and the log.
+pub fn mock_server<F>(respond: F) -> (String, ReceivedLog)
+where
+    F: Fn(&Received) -> (StatusCode, String) + Send + Sync + 'static,
+{
+    let log = ReceivedLog::default();
+    let respond = Arc::new(respond);
+    let router = Router::new().fallback({
+        let log = log.clone();
+        move |method: Method, uri: Uri, headers: HeaderMap, body: String| async move {
+            let received = Received {
+                method,
+                uri,
+                headers,
+                body,
+            };
+            let response = respond(&received);
+            log.lock().unwrap().push(received);
+            response
+        }
+    });
+    (serve(router), log)
+}
+
+/// [`mock_server`] answering every request with `200 {}`.
+pub fn mock_ok() -> (String, ReceivedLog) {
+    mock_server(|_| (StatusCode::OK, "{}".to_string()))
+}
+
+pub fn received(log: &ReceivedLog) -> Vec<Received> {
+    log.lock().unwrap().clone()
+}
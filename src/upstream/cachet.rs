@@ -0,0 +1,144 @@
+/*
+ ** Copyright (C) 2022 KunoiSayami
+ **
+ ** This program is free software: you can redistribute it and/or modify
+ ** it under the terms of the GNU Affero General Public License as published by
+ ** the Free Software Foundation, either version 3 of the License, or
+ ** any later version.
+ **
+ ** This program is distributed in the hope that it will be useful,
+ ** but WITHOUT ANY WARRANTY; without even the implied warranty of
+ ** MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ ** GNU Affero General Public License for more details.
+ **
+ ** You should have received a copy of the GNU Affero General Public License
+ ** along with this program. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::configure;
+use crate::datastructures::{Pushed, ServerLastStatus, StatusChange, UpstreamTrait};
+use anyhow::anyhow;
+use reqwest::header::{HeaderMap, HeaderValue};
+use reqwest::Client;
+use serde_json::json;
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Cachet component status codes, `None` for unknown status which Cachet has no code for.
+fn cachet_status(status: ServerLastStatus) -> Option<u8> {
+    match status {
+        ServerLastStatus::Optional => Some(1),
+        ServerLastStatus::DegradedPerformance => Some(2),
+        ServerLastStatus::PartialOutage => Some(3),
+        ServerLastStatus::Outage => Some(4),
+        ServerLastStatus::Unknown => None,
+    }
+}
+
+/// Update components of a self-hosted Cachet status page.
+pub struct CachetUpstream {
+    url: String,
+    components: HashMap<String, u64>,
+    client: Client,
+}
+
+impl CachetUpstream {
+    pub fn from_configure(cfg: &configure::CachetUpstream) -> anyhow::Result<Self> {
+        if cfg.token().is_empty() {
+            return Err(anyhow!("Cachet token is empty"));
+        }
+        let mut map = HeaderMap::new();
+        map.insert(
+            "X-Cachet-Token",
+            HeaderValue::from_str(cfg.token())
+                .map_err(|e| anyhow!("Invalid Cachet token: {:?}", e))?,
+        );
+        Ok(Self {
+            url: cfg.url().trim_end_matches('/').to_string(),
+            components: cfg.components().clone(),
+            client: reqwest::ClientBuilder::new()
+                .default_headers(map)
+                .timeout(Duration::from_secs(10))
+                .build()?,
+        })
+    }
+
+    pub fn build_request_url(&self, component_id: u64) -> String {
+        format!("{}/api/v1/components/{}", self.url, component_id)
+    }
+}
+
+#[async_trait::async_trait]
+impl UpstreamTrait for CachetUpstream {
+    fn name(&self) -> &str {
+        "cachet"
+    }
+
+    async fn set_component_status(&self, change: &StatusChange) -> anyhow::Result<Pushed> {
+        let (component_id, status) = match (
+            self.components.get(change.uuid()),
+            cachet_status(change.new_status()),
+        ) {
+            (Some(component_id), Some(status)) => (*component_id, status),
+            _ => return Ok(Pushed::Skipped),
+        };
+        self.client
+            .put(self.build_request_url(component_id))
+            .json(&json!({ "status": status }))
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(Pushed::Sent)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{change, mock_ok, received};
+
+    #[test]
+    fn test_cachet_status() {
+        assert_eq!(cachet_status(ServerLastStatus::Optional), Some(1));
+        assert_eq!(
+            cachet_status(ServerLastStatus::DegradedPerformance),
+            Some(2)
+        );
+        assert_eq!(cachet_status(ServerLastStatus::PartialOutage), Some(3));
+        assert_eq!(cachet_status(ServerLastStatus::Outage), Some(4));
+        assert_eq!(cachet_status(ServerLastStatus::Unknown), None);
+    }
+
+    #[tokio::test]
+    async fn test_update_component() {
+        let (url, log) = mock_ok();
+        let cfg: configure::CachetUpstream = toml::from_str(&format!(
+            "url = \"{}/\"\ntoken = \"key\"\n[components]\na = 7",
+            url
+        ))
+        .unwrap();
+        let cachet = CachetUpstream::from_configure(&cfg).unwrap();
+
+        cachet
+            .set_component_status(&change(
+                ServerLastStatus::Optional,
+                ServerLastStatus::PartialOutage,
+            ))
+            .await
+            .unwrap();
+        cachet
+            .set_component_status(&change(
+                ServerLastStatus::Optional,
+                ServerLastStatus::Unknown,
+            ))
+            .await
+            .unwrap();
+
+        let received = received(&log);
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].method, "PUT");
+        assert_eq!(received[0].uri.path(), "/api/v1/components/7");
+        assert_eq!(received[0].header("x-cachet-token"), "key");
+        assert_eq!(received[0].json(), json!({ "status": 3 }));
+    }
+}
@@ -0,0 +1,140 @@
+/*
+ ** Copyright (C) 2022 KunoiSayami
+ **
+ ** This program is free software: you can redistribute it and/or modify
+ ** it under the terms of the GNU Affero General Public License as published by
+ ** the Free Software Foundation, either version 3 of the License, or
+ ** any later version.
+ **
+ ** This program is distributed in the hope that it will be useful,
+ ** but WITHOUT ANY WARRANTY; without even the implied warranty of
+ ** MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ ** GNU Affero General Public License for more details.
+ **
+ ** You should have received a copy of the GNU Affero General Public License
+ ** along with this program. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use super::notify::{should_notify, Notification};
+use crate::configure::ChatNotifier;
+use crate::datastructures::{Pushed, StatusChange, UpstreamTrait};
+use chrono::NaiveDateTime;
+use reqwest::Client;
+use serde_json::json;
+use sqlx::SqliteConnection;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+/// Post status changes to a Discord channel webhook.
+pub struct DiscordNotifier {
+    name: String,
+    url: String,
+    components: Vec<String>,
+    client: Client,
+    conn: Arc<Mutex<SqliteConnection>>,
+}
+
+impl DiscordNotifier {
+    pub fn from_configure(
+        cfg: &ChatNotifier,
+        conn: Arc<Mutex<SqliteConnection>>,
+    ) -> anyhow::Result<Self> {
+        Ok(Self {
+            name: cfg.name().unwrap_or("discord").to_string(),
+            url: cfg.url().to_string(),
+            components: cfg.components().clone(),
+            client: reqwest::ClientBuilder::new()
+                .timeout(Duration::from_secs(10))
+                .build()?,
+            conn,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl UpstreamTrait for DiscordNotifier {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn set_component_status(&self, change: &StatusChange) -> anyhow::Result<Pushed> {
+        if !should_notify(&self.components, change) {
+            return Ok(Pushed::Skipped);
+        }
+        let notification = Notification::new(&self.conn, change).await;
+        // Embed colour is an integer instead of a css hex string.
+        let colour = u32::from_str_radix(notification.colour().trim_start_matches('#'), 16)?;
+        let timestamp = NaiveDateTime::from_timestamp_opt(change.timestamp() as i64, 0)
+            .map(|time| time.format("%Y-%m-%dT%H:%M:%SZ").to_string());
+        self.client
+            .post(&self.url)
+            .json(&json!({
+                "embeds": [{
+                    "title": notification.summary(),
+                    "description": format!("`{}` is now **{}**", change.uuid(), change.new_status().label()),
+                    "color": colour,
+                    "timestamp": timestamp,
+                }],
+            }))
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(Pushed::Sent)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datastructures::ServerLastStatus;
+    use crate::test_util::{change, component, memory_database, mock_ok, received, TIMESTAMP};
+
+    #[tokio::test]
+    async fn test_post() {
+        let (url, log) = mock_ok();
+        let cfg: ChatNotifier = toml::from_str(&format!(
+            "name = \"ops\"\nurl = \"{}/api/webhooks/1/x\"\ncomponents = [\"b\"]",
+            url
+        ))
+        .unwrap();
+        let discord =
+            DiscordNotifier::from_configure(&cfg, Arc::new(Mutex::new(memory_database().await)))
+                .unwrap();
+        assert_eq!(discord.name(), "ops");
+
+        // Component `a` is not routed to this channel.
+        let down = change(ServerLastStatus::Optional, ServerLastStatus::Outage);
+        assert_eq!(
+            discord.set_component_status(&down).await.unwrap(),
+            Pushed::Skipped
+        );
+        let routed = StatusChange::new(
+            &component("b", "DB"),
+            ServerLastStatus::Optional,
+            ServerLastStatus::PartialOutage,
+            TIMESTAMP,
+        );
+        assert_eq!(
+            discord.set_component_status(&routed).await.unwrap(),
+            Pushed::Sent
+        );
+
+        let received = received(&log);
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].method, "POST");
+        assert_eq!(received[0].uri.path(), "/api/webhooks/1/x");
+        assert_eq!(
+            received[0].json(),
+            json!({
+                "embeds": [{
+                    "title": "DB operational → partial outage",
+                    "description": "`b` is now **partial outage**",
+                    // #e67e22 as an integer.
+                    "color": 0xe67e22,
+                    "timestamp": "2020-09-13T12:26:40Z",
+                }],
+            })
+        );
+    }
+}
@@ -0,0 +1,203 @@
+/*
+ ** Copyright (C) 2022 KunoiSayami
+ **
+ ** This program is free software: you can redistribute it and/or modify
+ ** it under the terms of the GNU Affero General Public License as published by
+ ** the Free Software Foundation, either version 3 of the License, or
+ ** any later version.
+ **
+ ** This program is distributed in the hope that it will be useful,
+ ** but WITHOUT ANY WARRANTY; without even the implied warranty of
+ ** MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ ** GNU Affero General Public License for more details.
+ **
+ ** You should have received a copy of the GNU Affero General Public License
+ ** along with this program. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use super::notify::{should_notify, Notification};
+use crate::configure;
+use crate::datastructures::{Pushed, StatusChange, UpstreamTrait};
+use anyhow::anyhow;
+use chrono::NaiveDateTime;
+use lettre::message::header::ContentType;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor};
+use sqlx::SqliteConnection;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+fn parse_mailboxes(addresses: &[String]) -> anyhow::Result<Vec<Mailbox>> {
+    addresses
+        .iter()
+        .map(|address| {
+            address
+                .parse()
+                .map_err(|e| anyhow!("Invalid email address {:?}: {:?}", address, e))
+        })
+        .collect()
+}
+
+/// Mail status transitions through an SMTP relay.
+pub struct EmailNotifier {
+    from: Mailbox,
+    recipients: Vec<Mailbox>,
+    components: HashMap<String, Vec<Mailbox>>,
+    public_url: Option<String>,
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    conn: Arc<Mutex<SqliteConnection>>,
+}
+
+impl EmailNotifier {
+    pub fn from_configure(
+        cfg: &configure::EmailNotifier,
+        conn: Arc<Mutex<SqliteConnection>>,
+    ) -> anyhow::Result<Self> {
+        let mut transport = if cfg.starttls() {
+            AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(cfg.host())?
+        } else {
+            AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(cfg.host())
+        }
+        .port(cfg.port())
+        .timeout(Some(Duration::from_secs(10)));
+        if let Some(username) = cfg.username() {
+            transport = transport.credentials(Credentials::new(
+                username.to_string(),
+                cfg.password().unwrap_or_default().to_string(),
+            ));
+        }
+
+        let components = cfg
+            .components()
+            .iter()
+            .map(|(uuid, addresses)| Ok((uuid.clone(), parse_mailboxes(addresses)?)))
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Self {
+            from: cfg
+                .from()
+                .parse()
+                .map_err(|e| anyhow!("Invalid email address {:?}: {:?}", cfg.from(), e))?,
+            recipients: parse_mailboxes(cfg.recipients())?,
+            components,
+            public_url: cfg.public_url().map(|s| s.to_string()),
+            transport: transport.build(),
+            conn,
+        })
+    }
+
+    /// Recipients of component `uuid`, the shared list unless it has its own.
+    fn recipients(&self, uuid: &str) -> &Vec<Mailbox> {
+        self.components.get(uuid).unwrap_or(&self.recipients)
+    }
+
+    fn build_body(&self, notification: &Notification<'_>, change: &StatusChange) -> String {
+        let time = NaiveDateTime::from_timestamp_opt(change.timestamp() as i64, 0)
+            .map(|time| time.format("%Y-%m-%d %H:%M:%S UTC").to_string())
+            .unwrap_or_default();
+        let mut body = format!(
+            "{}\n\nComponent: {} ({})\nTransition: {} → {}\nTime: {}\n",
+            notification.summary(),
+            change.name(),
+            change.uuid(),
+            change.old_status().label(),
+            change.new_status().label(),
+            time
+        );
+        if let Some(ref url) = self.public_url {
+            body.push_str(&format!("Status page: {}\n", url));
+        }
+        body
+    }
+}
+
+#[async_trait::async_trait]
+impl UpstreamTrait for EmailNotifier {
+    fn name(&self) -> &str {
+        "email"
+    }
+
+    async fn set_component_status(&self, change: &StatusChange) -> anyhow::Result<Pushed> {
+        let recipients = self.recipients(change.uuid());
+        if recipients.is_empty() || !should_notify(&[], change) {
+            return Ok(Pushed::Skipped);
+        }
+        let notification = Notification::new(&self.conn, change).await;
+        let mut message = Message::builder()
+            .from(self.from.clone())
+            .subject(notification.summary());
+        for recipient in recipients {
+            message = message.to(recipient.clone());
+        }
+        let message = message
+            .header(ContentType::TEXT_PLAIN)
+            .body(self.build_body(&notification, change))?;
+        self.transport.send(message).await?;
+        Ok(Pushed::Sent)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datastructures::ServerLastStatus;
+    use crate::test_util::{change, memory_database};
+
+    async fn notifier(extra: &str) -> EmailNotifier {
+        let cfg: configure::EmailNotifier = toml::from_str(&format!(
+            "host = \"localhost\"\nstarttls = false\nfrom = \"status@example.com\"\n\
+            recipients = [\"ops@example.com\", \"dev@example.com\"]\n{}\n\
+            [components]\nb = [\"db@example.com\"]",
+            extra
+        ))
+        .unwrap();
+        EmailNotifier::from_configure(&cfg, Arc::new(Mutex::new(memory_database().await))).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_build_body() {
+        let down = change(ServerLastStatus::Optional, ServerLastStatus::Outage);
+        let email = notifier("public_url = \"https://status.example.com\"").await;
+        let notification = Notification::new(&email.conn, &down).await;
+        assert_eq!(
+            email.build_body(&notification, &down),
+            "Web operational → major outage\n\n\
+            Component: Web (a)\n\
+            Transition: operational → major outage\n\
+            Time: 2020-09-13 12:26:40 UTC\n\
+            Status page: https://status.example.com\n"
+        );
+
+        // Without public_url the link is left out.
+        let email = notifier("").await;
+        assert!(!email
+            .build_body(&notification, &down)
+            .contains("Status page"));
+    }
+
+    #[tokio::test]
+    async fn test_recipients() {
+        let email = notifier("").await;
+        let addresses = |uuid| {
+            email
+                .recipients(uuid)
+                .iter()
+                .map(|mailbox| mailbox.email.to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(addresses("a"), ["ops@example.com", "dev@example.com"]);
+        assert_eq!(addresses("b"), ["db@example.com"]);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_address() {
+        let cfg: configure::EmailNotifier = toml::from_str(
+            "host = \"localhost\"\nfrom = \"status@example.com\"\nrecipients = [\"nobody\"]",
+        )
+        .unwrap();
+        let conn = Arc::new(Mutex::new(memory_database().await));
+        assert!(EmailNotifier::from_configure(&cfg, conn).is_err());
+    }
+}
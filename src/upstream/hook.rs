@@ -0,0 +1,294 @@
+/*
+ ** Copyright (C) 2022 KunoiSayami
+ **
+ ** This program is free software: you can redistribute it and/or modify
+ ** it under the terms of the GNU Affero General Public License as published by
+ ** the Free Software Foundation, either version 3 of the License, or
+ ** any later version.
+ **
+ ** This program is distributed in the hope that it will be useful,
+ ** but WITHOUT ANY WARRANTY; without even the implied warranty of
+ ** MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ ** GNU Affero General Public License for more details.
+ **
+ ** You should have received a copy of the GNU Affero General Public License
+ ** along with this program. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use super::notify::should_notify;
+use crate::configure;
+use crate::datastructures::{Pushed, StatusChange, UpstreamTrait};
+use anyhow::anyhow;
+#[cfg(any(feature = "env_logger", feature = "log4rs"))]
+use log::{error, info};
+use serde_json::json;
+#[cfg(feature = "spdlog-rs")]
+use spdlog::prelude::*;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+use tokio::sync::mpsc;
+
+/// Changes waiting for a hook program, pushes fail once it is full so the outbox retries them.
+const QUEUE_SIZE: usize = 64;
+
+/// Run a local program on every status transition.
+///
+/// The change is passed as `STATUS_UPSTREAM_*` environment variables and as JSON on stdin.
+/// Programs run one at a time in a background worker of each hook, in the order of changes,
+/// so a slow one never holds up the report. A non-zero exit or timeout is only logged.
+pub struct HookUpstream {
+    name: String,
+    components: Vec<String>,
+    queue: mpsc::Sender<StatusChange>,
+}
+
+struct HookProgram {
+    name: String,
+    command: String,
+    args: Vec<String>,
+    timeout: Duration,
+}
+
+impl HookUpstream {
+    pub fn from_configure(cfg: &configure::HookUpstream) -> Self {
+        let program = HookProgram {
+            name: cfg.name().to_string(),
+            command: cfg.command().to_string(),
+            args: cfg.args().clone(),
+            timeout: Duration::from_secs(cfg.timeout()),
+        };
+        let (queue, mut receiver) = mpsc::channel::<StatusChange>(QUEUE_SIZE);
+        tokio::spawn(async move {
+            while let Some(change) = receiver.recv().await {
+                program
+                    .run(&change)
+                    .await
+                    .unwrap_or_else(|e| error!("Run hook for {} error: {:?}", change.uuid(), e));
+            }
+        });
+        Self {
+            name: cfg.name().to_string(),
+            components: cfg.components().clone(),
+            queue,
+        }
+    }
+}
+
+impl HookProgram {
+    async fn run(&self, change: &StatusChange) -> anyhow::Result<()> {
+        let mut child = Command::new(&self.command)
+            .args(&self.args)
+            .env("STATUS_UPSTREAM_UUID", change.uuid())
+            .env("STATUS_UPSTREAM_NAME", change.name())
+            .env(
+                "STATUS_UPSTREAM_OLD_STATUS",
+                change.old_status().to_string(),
+            )
+            .env(
+                "STATUS_UPSTREAM_NEW_STATUS",
+                change.new_status().to_string(),
+            )
+            .env("STATUS_UPSTREAM_TIMESTAMP", change.timestamp().to_string())
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .map_err(|e| anyhow!("Spawn hook {} error: {:?}", self.name, e))?;
+
+        let payload = json!({
+            "uuid": change.uuid(),
+            "name": change.name(),
+            "old_status": change.old_status().to_string(),
+            "new_status": change.new_status().to_string(),
+            "timestamp": change.timestamp(),
+        })
+        .to_string();
+        let mut stdin = child.stdin.take().expect("stdin is piped");
+        // Program may exit without reading stdin, the exit status tells the result.
+        stdin.write_all(payload.as_bytes()).await.ok();
+        drop(stdin);
+
+        let output = tokio::time::timeout(self.timeout, child.wait_with_output())
+            .await
+            .map_err(|_| {
+                anyhow!(
+                    "Hook {} timed out after {}s",
+                    self.name,
+                    self.timeout.as_secs()
+                )
+            })??;
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        if output.status.success() {
+            info!(
+                "Hook {} for {} exited with {}, stdout: {:?}",
+                self.name,
+                change.uuid(),
+                output.status,
+                stdout.trim()
+            );
+            Ok(())
+        } else {
+            Err(anyhow!(
+                "Hook {} exited with {}, stdout: {:?}, stderr: {:?}",
+                self.name,
+                output.status,
+                stdout.trim(),
+                stderr.trim()
+            ))
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl UpstreamTrait for HookUpstream {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn set_component_status(&self, change: &StatusChange) -> anyhow::Result<Pushed> {
+        if !should_notify(&self.components, change) {
+            return Ok(Pushed::Skipped);
+        }
+        self.queue
+            .try_send(change.clone())
+            .map_err(|e| anyhow!("Queue hook {} error: {}", self.name, e))?;
+        Ok(Pushed::Sent)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datastructures::ServerLastStatus;
+    use crate::test_util::{change, TIMESTAMP};
+    use std::path::PathBuf;
+
+    /// Run `script` with `sh -c`, `$0` is a scratch file unique to `test`.
+    fn program(test: &str, script: &str, timeout: u64) -> (HookProgram, PathBuf) {
+        let path = std::env::temp_dir().join(format!(
+            "status-upstream-hook-{}-{}",
+            std::process::id(),
+            test
+        ));
+        std::fs::remove_file(&path).ok();
+        let program = HookProgram {
+            name: "test".to_string(),
+            command: "sh".to_string(),
+            args: vec![
+                "-c".to_string(),
+                script.to_string(),
+                path.to_str().unwrap().to_string(),
+            ],
+            timeout: Duration::from_secs(timeout),
+        };
+        (program, path)
+    }
+
+    #[tokio::test]
+    async fn test_env_and_stdin() {
+        let (program, path) = program(
+            "env",
+            r#"echo "$STATUS_UPSTREAM_UUID|$STATUS_UPSTREAM_NAME|$STATUS_UPSTREAM_OLD_STATUS|$STATUS_UPSTREAM_NEW_STATUS|$STATUS_UPSTREAM_TIMESTAMP" > "$0"; cat >> "$0""#,
+            10,
+        );
+        program
+            .run(&change(
+                ServerLastStatus::Optional,
+                ServerLastStatus::Outage,
+            ))
+            .await
+            .unwrap();
+
+        let output = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let (env, stdin) = output.split_once('\n').unwrap();
+        assert_eq!(env, format!("a|Web|operational|major_outage|{}", TIMESTAMP));
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(stdin).unwrap(),
+            json!({
+                "uuid": "a",
+                "name": "Web",
+                "old_status": "operational",
+                "new_status": "major_outage",
+                "timestamp": TIMESTAMP,
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_non_zero_exit() {
+        let (program, _) = program("exit", "echo out; echo err >&2; exit 3", 10);
+        let e = program
+            .run(&change(
+                ServerLastStatus::Optional,
+                ServerLastStatus::Outage,
+            ))
+            .await
+            .unwrap_err()
+            .to_string();
+        assert!(
+            e.starts_with("Hook test exited with exit status: 3"),
+            "{}",
+            e
+        );
+        assert!(e.contains(r#"stdout: "out""#), "{}", e);
+        assert!(e.contains(r#"stderr: "err""#), "{}", e);
+    }
+
+    #[tokio::test]
+    async fn test_timeout_kills_program() {
+        let (program, path) = program("timeout", r#"echo $$ > "$0"; exec sleep 30"#, 1);
+        let e = program
+            .run(&change(
+                ServerLastStatus::Optional,
+                ServerLastStatus::Outage,
+            ))
+            .await
+            .unwrap_err();
+        assert_eq!(e.to_string(), "Hook test timed out after 1s");
+
+        let pid = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        // Killed program is gone, or a zombie until tokio reaps it.
+        let state = std::fs::read_to_string(format!("/proc/{}/stat", pid.trim()));
+        assert!(state.map_or(true, |state| state.contains(") Z ")));
+    }
+
+    #[tokio::test]
+    async fn test_queue_keeps_order() {
+        let (_, path) = program("order", "", 10);
+        let cfg: configure::HookUpstream = toml::from_str(&format!(
+            "name = \"test\"\ncommand = \"sh\"\nargs = [\"-c\", '[ $STATUS_UPSTREAM_NEW_STATUS = major_outage ] && sleep 0.5; echo $STATUS_UPSTREAM_NEW_STATUS >> \"$0\"', \"{}\"]",
+            path.to_str().unwrap()
+        ))
+        .unwrap();
+        let hook = HookUpstream::from_configure(&cfg);
+        // The first change runs slowest, later ones must still wait for it.
+        for (old, new) in [
+            (ServerLastStatus::Optional, ServerLastStatus::Outage),
+            (ServerLastStatus::Outage, ServerLastStatus::PartialOutage),
+            (ServerLastStatus::PartialOutage, ServerLastStatus::Optional),
+        ] {
+            assert_eq!(
+                hook.set_component_status(&change(old, new)).await.unwrap(),
+                Pushed::Sent
+            );
+        }
+
+        let mut output = String::new();
+        for _ in 0..50 {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            output = std::fs::read_to_string(&path).unwrap_or_default();
+            if output.lines().count() == 3 {
+                break;
+            }
+        }
+        std::fs::remove_file(&path).ok();
+        assert_eq!(output, "major_outage\npartial_outage\noperational\n");
+    }
+}
@@ -0,0 +1,158 @@
+/*
+ ** Copyright (C) 2022 KunoiSayami
+ **
+ ** This program is free software: you can redistribute it and/or modify
+ ** it under the terms of the GNU Affero General Public License as published by
+ ** the Free Software Foundation, either version 3 of the License, or
+ ** any later version.
+ **
+ ** This program is distributed in the hope that it will be useful,
+ ** but WITHOUT ANY WARRANTY; without even the implied warranty of
+ ** MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ ** GNU Affero General Public License for more details.
+ **
+ ** You should have received a copy of the GNU Affero General Public License
+ ** along with this program. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::configure;
+use crate::datastructures::{Pushed, ServerLastStatus, StatusChange, UpstreamTrait};
+use anyhow::anyhow;
+use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION};
+use reqwest::Client;
+use serde_json::json;
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Instatus component status, `None` for unknown status which Instatus has no value for.
+fn instatus_status(status: ServerLastStatus) -> Option<&'static str> {
+    match status {
+        ServerLastStatus::Optional => Some("OPERATIONAL"),
+        ServerLastStatus::DegradedPerformance => Some("DEGRADEDPERFORMANCE"),
+        ServerLastStatus::PartialOutage => Some("PARTIALOUTAGE"),
+        ServerLastStatus::Outage => Some("MAJOROUTAGE"),
+        ServerLastStatus::Unknown => None,
+    }
+}
+
+/// Update components of an Instatus page.
+pub struct InstatusUpstream {
+    url: String,
+    page_id: String,
+    components: HashMap<String, String>,
+    client: Client,
+}
+
+impl InstatusUpstream {
+    pub fn from_configure(cfg: &configure::InstatusUpstream) -> anyhow::Result<Self> {
+        if cfg.api_key().is_empty() {
+            return Err(anyhow!("Instatus api_key is empty"));
+        }
+        let mut map = HeaderMap::new();
+        map.insert(
+            AUTHORIZATION,
+            HeaderValue::from_str(&format!("Bearer {}", cfg.api_key()))
+                .map_err(|e| anyhow!("Invalid Instatus api_key: {:?}", e))?,
+        );
+        Ok(Self {
+            url: cfg.url().trim_end_matches('/').to_string(),
+            page_id: cfg.page_id().to_string(),
+            components: cfg.components().clone(),
+            client: reqwest::ClientBuilder::new()
+                .default_headers(map)
+                .timeout(Duration::from_secs(10))
+                .build()?,
+        })
+    }
+
+    pub fn build_request_url(&self, component_id: &str) -> String {
+        format!(
+            "{}/v1/{}/components/{}",
+            self.url, self.page_id, component_id
+        )
+    }
+}
+
+#[async_trait::async_trait]
+impl UpstreamTrait for InstatusUpstream {
+    fn name(&self) -> &str {
+        "instatus"
+    }
+
+    async fn set_component_status(&self, change: &StatusChange) -> anyhow::Result<Pushed> {
+        let (component_id, status) = match (
+            self.components.get(change.uuid()),
+            instatus_status(change.new_status()),
+        ) {
+            (Some(component_id), Some(status)) => (component_id, status),
+            _ => return Ok(Pushed::Skipped),
+        };
+        self.client
+            .put(self.build_request_url(component_id))
+            .json(&json!({ "status": status }))
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(Pushed::Sent)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{change, mock_ok, received};
+
+    #[test]
+    fn test_instatus_status() {
+        assert_eq!(
+            instatus_status(ServerLastStatus::Optional),
+            Some("OPERATIONAL")
+        );
+        assert_eq!(
+            instatus_status(ServerLastStatus::DegradedPerformance),
+            Some("DEGRADEDPERFORMANCE")
+        );
+        assert_eq!(
+            instatus_status(ServerLastStatus::PartialOutage),
+            Some("PARTIALOUTAGE")
+        );
+        assert_eq!(
+            instatus_status(ServerLastStatus::Outage),
+            Some("MAJOROUTAGE")
+        );
+        assert_eq!(instatus_status(ServerLastStatus::Unknown), None);
+    }
+
+    #[tokio::test]
+    async fn test_update_component() {
+        let (url, log) = mock_ok();
+        let cfg: configure::InstatusUpstream = toml::from_str(&format!(
+            "api_key = \"key\"\npage_id = \"p1\"\nurl = \"{}/\"\n[components]\na = \"c1\"",
+            url
+        ))
+        .unwrap();
+        let instatus = InstatusUpstream::from_configure(&cfg).unwrap();
+
+        instatus
+            .set_component_status(&change(
+                ServerLastStatus::Optional,
+                ServerLastStatus::Outage,
+            ))
+            .await
+            .unwrap();
+        instatus
+            .set_component_status(&change(
+                ServerLastStatus::Optional,
+                ServerLastStatus::Unknown,
+            ))
+            .await
+            .unwrap();
+
+        let received = received(&log);
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].method, "PUT");
+        assert_eq!(received[0].uri.path(), "/v1/p1/components/c1");
+        assert_eq!(received[0].header("authorization"), "Bearer key");
+        assert_eq!(received[0].json(), json!({ "status": "MAJOROUTAGE" }));
+    }
+}
@@ -0,0 +1,198 @@
+/*
+ ** Copyright (C) 2022 KunoiSayami
+ **
+ ** This program is free software: you can redistribute it and/or modify
+ ** it under the terms of the GNU Affero General Public License as published by
+ ** the Free Software Foundation, either version 3 of the License, or
+ ** any later version.
+ **
+ ** This program is distributed in the hope that it will be useful,
+ ** but WITHOUT ANY WARRANTY; without even the implied warranty of
+ ** MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ ** GNU Affero General Public License for more details.
+ **
+ ** You should have received a copy of the GNU Affero General Public License
+ ** along with this program. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use super::notify::{should_notify, Notification};
+use crate::configure;
+use crate::datastructures::{Pushed, StatusChange, UpstreamTrait};
+use crate::web_service::v1::escape_html;
+use anyhow::anyhow;
+use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION};
+use reqwest::{Client, Url};
+use serde_json::json;
+use sqlx::SqliteConnection;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+/// Post status changes to Matrix rooms through the client-server API.
+pub struct MatrixNotifier {
+    homeserver: Url,
+    rooms: Vec<String>,
+    components: HashMap<String, Vec<String>>,
+    client: Client,
+    conn: Arc<Mutex<SqliteConnection>>,
+}
+
+impl MatrixNotifier {
+    pub fn from_configure(
+        cfg: &configure::MatrixNotifier,
+        conn: Arc<Mutex<SqliteConnection>>,
+    ) -> anyhow::Result<Self> {
+        let homeserver = Url::parse(cfg.homeserver())
+            .map_err(|e| anyhow!("Invalid homeserver {:?}: {:?}", cfg.homeserver(), e))?;
+        if homeserver.cannot_be_a_base() {
+            return Err(anyhow!("Invalid homeserver {:?}", cfg.homeserver()));
+        }
+        let mut map = HeaderMap::new();
+        map.insert(
+            AUTHORIZATION,
+            HeaderValue::from_str(&format!("Bearer {}", cfg.access_token()))
+                .map_err(|e| anyhow!("Invalid Matrix access_token: {:?}", e))?,
+        );
+        Ok(Self {
+            homeserver,
+            rooms: cfg.rooms().clone(),
+            components: cfg.components().clone(),
+            client: reqwest::ClientBuilder::new()
+                .default_headers(map)
+                .timeout(Duration::from_secs(10))
+                .build()?,
+            conn,
+        })
+    }
+
+    /// Transaction id is derived from the change, so a retried message is deduplicated
+    /// by the homeserver.
+    pub fn build_request_url(&self, room: &str, change: &StatusChange) -> Url {
+        let txn_id = Uuid::new_v5(
+            &Uuid::NAMESPACE_OID,
+            format!(
+                "{}/{}/{}/{}/{}",
+                room,
+                change.uuid(),
+                change.old_status(),
+                change.new_status(),
+                change.timestamp()
+            )
+            .as_bytes(),
+        )
+        .to_string();
+        let mut url = self.homeserver.clone();
+        url.path_segments_mut()
+            .expect("Checked in from_configure")
+            .pop_if_empty()
+            .extend([
+                "_matrix",
+                "client",
+                "v3",
+                "rooms",
+                room,
+                "send",
+                "m.room.message",
+                &txn_id,
+            ]);
+        url
+    }
+}
+
+#[async_trait::async_trait]
+impl UpstreamTrait for MatrixNotifier {
+    fn name(&self) -> &str {
+        "matrix"
+    }
+
+    async fn set_component_status(&self, change: &StatusChange) -> anyhow::Result<Pushed> {
+        let rooms = self.components.get(change.uuid()).unwrap_or(&self.rooms);
+        if rooms.is_empty() || !should_notify(&[], change) {
+            return Ok(Pushed::Skipped);
+        }
+        let notification = Notification::new(&self.conn, change).await;
+        let summary = notification.summary();
+        let content = json!({
+            "msgtype": "m.text",
+            "body": summary,
+            "format": "org.matrix.custom.html",
+            "formatted_body": format!(
+                "<font color=\"{}\">●</font> {}",
+                notification.colour(),
+                escape_html(&summary)
+            ),
+        });
+        for room in rooms {
+            self.client
+                .put(self.build_request_url(room, change))
+                .json(&content)
+                .send()
+                .await?
+                .error_for_status()?;
+        }
+        Ok(Pushed::Sent)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datastructures::ServerLastStatus;
+    use crate::test_util::{change, memory_database, mock_ok, received};
+
+    async fn matrix(homeserver: &str) -> MatrixNotifier {
+        let cfg: configure::MatrixNotifier = toml::from_str(&format!(
+            "homeserver = \"{}\"\naccess_token = \"tk\"\nrooms = [\"!ops:example.com\"]\n\
+            [components]\nb = [\"!db:example.com\"]",
+            homeserver
+        ))
+        .unwrap();
+        MatrixNotifier::from_configure(&cfg, Arc::new(Mutex::new(memory_database().await))).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_build_request_url() {
+        let down = change(ServerLastStatus::Optional, ServerLastStatus::Outage);
+        let url = matrix("https://matrix.example.com/")
+            .await
+            .build_request_url("!ops:example.com", &down);
+        assert!(url
+            .as_str()
+            .starts_with("https://matrix.example.com/_matrix/client/v3/rooms/!ops:example.com/send/m.room.message/"));
+        // Retried message keeps its transaction id, another room gets its own.
+        let matrix = matrix("https://matrix.example.com").await;
+        assert_eq!(url, matrix.build_request_url("!ops:example.com", &down));
+        assert_ne!(url, matrix.build_request_url("!db:example.com", &down));
+    }
+
+    #[tokio::test]
+    async fn test_send() {
+        let (url, log) = mock_ok();
+        let matrix = matrix(&url).await;
+        let down = change(ServerLastStatus::Optional, ServerLastStatus::Outage);
+        assert_eq!(
+            matrix.set_component_status(&down).await.unwrap(),
+            Pushed::Sent
+        );
+
+        let received = received(&log);
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].method, "PUT");
+        assert_eq!(
+            received[0].uri.path(),
+            matrix.build_request_url("!ops:example.com", &down).path()
+        );
+        assert_eq!(received[0].header("authorization"), "Bearer tk");
+        assert_eq!(
+            received[0].json(),
+            json!({
+                "msgtype": "m.text",
+                "body": "Web operational → major outage",
+                "format": "org.matrix.custom.html",
+                "formatted_body": "<font color=\"#e74c3c\">●</font> Web operational → major outage",
+            })
+        );
+    }
+}
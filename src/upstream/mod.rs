@@ -0,0 +1,43 @@
+/*
+ ** Copyright (C) 2022 KunoiSayami
+ **
+ ** This program is free software: you can redistribute it and/or modify
+ ** it under the terms of the GNU Affero General Public License as published by
+ ** the Free Software Foundation, either version 3 of the License, or
+ ** any later version.
+ **
+ ** This program is distributed in the hope that it will be useful,
+ ** but WITHOUT ANY WARRANTY; without even the implied warranty of
+ ** MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ ** GNU Affero General Public License for more details.
+ **
+ ** You should have received a copy of the GNU Affero General Public License
+ ** along with this program. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+mod cachet;
+mod discord;
+mod email;
+mod hook;
+mod instatus;
+mod matrix;
+mod notify;
+mod pagerduty;
+mod push;
+mod slack;
+mod telegram;
+mod uptime_kuma;
+mod webhook;
+
+pub use cachet::CachetUpstream;
+pub use discord::DiscordNotifier;
+pub use email::EmailNotifier;
+pub use hook::HookUpstream;
+pub use instatus::InstatusUpstream;
+pub use matrix::MatrixNotifier;
+pub use pagerduty::PagerDutyUpstream;
+pub use push::{GotifyUpstream, NtfyUpstream};
+pub use slack::SlackNotifier;
+pub use telegram::TelegramNotifier;
+pub use uptime_kuma::UptimeKumaUpstream;
+pub use webhook::WebhookUpstream;
@@ -0,0 +1,155 @@
+/*
+ ** Copyright (C) 2022 KunoiSayami
+ **
+ ** This program is free software: you can redistribute it and/or modify
+ ** it under the terms of the GNU Affero General Public License as published by
+ ** the Free Software Foundation, either version 3 of the License, or
+ ** any later version.
+ **
+ ** This program is distributed in the hope that it will be useful,
+ ** but WITHOUT ANY WARRANTY; without even the implied warranty of
+ ** MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ ** GNU Affero General Public License for more details.
+ **
+ ** You should have received a copy of the GNU Affero General Public License
+ ** along with this program. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::database::incident_started;
+use crate::datastructures::{ServerLastStatus, StatusChange};
+#[cfg(any(feature = "env_logger", feature = "log4rs"))]
+use log::error;
+#[cfg(feature = "spdlog-rs")]
+use spdlog::prelude::*;
+use sqlx::SqliteConnection;
+use tokio::sync::Mutex;
+
+/// Human readable form of a status change shared by the chat notifiers.
+pub struct Notification<'a> {
+    change: &'a StatusChange,
+    /// Seconds since the component left `operational`, only set on recovery.
+    downtime: Option<u64>,
+}
+
+impl<'a> Notification<'a> {
+    /// Look up how long the component was down if it just recovered.
+    pub async fn new(conn: &Mutex<SqliteConnection>, change: &'a StatusChange) -> Notification<'a> {
+        let downtime = if change.new_status() == ServerLastStatus::Optional {
+            let mut conn = conn.lock().await;
+            incident_started(&mut conn, change.uuid(), change.timestamp())
+                .await
+                .map_err(|e| error!("{:?}", e))
+                .ok()
+                .flatten()
+                .map(|started| change.timestamp().saturating_sub(started))
+        } else {
+            None
+        };
+        Self { change, downtime }
+    }
+
+    /// e.g. `API degraded → operational after 14m`
+    pub fn summary(&self) -> String {
+        let mut summary = format!(
+            "{} {} → {}",
+            self.change.name(),
+            self.change.old_status().label(),
+            self.change.new_status().label()
+        );
+        if let Some(downtime) = self.downtime {
+            summary.push_str(" after ");
+            summary.push_str(&format_duration(downtime));
+        }
+        summary
+    }
+
+    pub fn colour(&self) -> &'static str {
+        self.change.new_status().colour()
+    }
+}
+
+/// Resync pushes the same status again, only real transitions of routed components notify.
+pub fn should_notify(components: &[String], change: &StatusChange) -> bool {
+    change.old_status() != change.new_status()
+        && (components.is_empty() || components.iter().any(|uuid| uuid == change.uuid()))
+}
+
+/// Two most significant units, e.g. `45s`, `14m`, `2h 5m`, `3d 4h`.
+pub fn format_duration(seconds: u64) -> String {
+    let units = [(86400, "d"), (3600, "h"), (60, "m"), (1, "s")];
+    let parts = units
+        .iter()
+        .scan(seconds, |rest, &(size, unit)| {
+            let value = *rest / size;
+            *rest %= size;
+            Some((value, unit))
+        })
+        .skip_while(|(value, _)| *value == 0)
+        .take(2)
+        .filter(|(value, _)| *value > 0)
+        .map(|(value, unit)| format!("{}{}", value, unit))
+        .collect::<Vec<_>>();
+    if parts.is_empty() {
+        "0s".to_string()
+    } else {
+        parts.join(" ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{change, memory_database, TIMESTAMP};
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(0), "0s");
+        assert_eq!(format_duration(45), "45s");
+        assert_eq!(format_duration(840), "14m");
+        assert_eq!(format_duration(845), "14m 5s");
+        assert_eq!(format_duration(7500), "2h 5m");
+        // Only the two most significant units are kept.
+        assert_eq!(format_duration(7505), "2h 5m");
+        assert_eq!(format_duration(3 * 86400 + 4 * 3600 + 59), "3d 4h");
+        assert_eq!(format_duration(86400 + 30), "1d");
+    }
+
+    #[tokio::test]
+    async fn test_summary() {
+        let conn = Mutex::new(memory_database().await);
+        let down = change(ServerLastStatus::Optional, ServerLastStatus::Outage);
+        assert_eq!(
+            Notification::new(&conn, &down).await.summary(),
+            "Web operational → major outage"
+        );
+
+        sqlx::query(
+            r#"INSERT INTO "history" ("uuid", "old_status", "new_status", "timestamp", "source")
+                VALUES ('a', 'operational', 'major_outage', ?, 'report'),
+                ('a', 'major_outage', 'partial_outage', ?, 'report')"#,
+        )
+        .bind(TIMESTAMP as i64 - 840)
+        .bind(TIMESTAMP as i64 - 60)
+        .execute(&mut *conn.lock().await)
+        .await
+        .unwrap();
+        // Downtime counts from leaving operational, not from the last transition.
+        let recovered = change(ServerLastStatus::PartialOutage, ServerLastStatus::Optional);
+        let notification = Notification::new(&conn, &recovered).await;
+        assert_eq!(
+            notification.summary(),
+            "Web partial outage → operational after 14m"
+        );
+        assert_eq!(notification.colour(), "#2fcc66");
+    }
+
+    #[test]
+    fn test_should_notify() {
+        let down = change(ServerLastStatus::Optional, ServerLastStatus::Outage);
+        assert!(should_notify(&[], &down));
+        assert!(should_notify(&["a".to_string()], &down));
+        assert!(!should_notify(&["b".to_string()], &down));
+        let unchanged = change(ServerLastStatus::Outage, ServerLastStatus::Outage);
+        assert!(!should_notify(&[], &unchanged));
+    }
+}
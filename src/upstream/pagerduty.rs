@@ -0,0 +1,239 @@
+/*
+ ** Copyright (C) 2022 KunoiSayami
+ **
+ ** This program is free software: you can redistribute it and/or modify
+ ** it under the terms of the GNU Affero General Public License as published by
+ ** the Free Software Foundation, either version 3 of the License, or
+ ** any later version.
+ **
+ ** This program is distributed in the hope that it will be useful,
+ ** but WITHOUT ANY WARRANTY; without even the implied warranty of
+ ** MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ ** GNU Affero General Public License for more details.
+ **
+ ** You should have received a copy of the GNU Affero General Public License
+ ** along with this program. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::configure;
+use crate::datastructures::{Pushed, ServerLastStatus, StatusChange, UpstreamTrait};
+use anyhow::anyhow;
+use chrono::NaiveDateTime;
+use reqwest::Client;
+use serde_json::json;
+use std::collections::HashMap;
+use std::time::Duration;
+
+const SEVERITIES: [&str; 4] = ["critical", "error", "warning", "info"];
+
+/// Open a PagerDuty alert through the Events API v2 while a component is down,
+/// the component uuid is the dedup key so the recovery resolves the same alert.
+pub struct PagerDutyUpstream {
+    routing_key: String,
+    url: String,
+    severity: HashMap<ServerLastStatus, String>,
+    client: Client,
+}
+
+impl PagerDutyUpstream {
+    pub fn from_configure(cfg: &configure::PagerDutyUpstream) -> anyhow::Result<Self> {
+        let mut severity = HashMap::new();
+        for (status, level) in cfg.severity() {
+            let status = match ServerLastStatus::try_from(status)? {
+                ServerLastStatus::Unknown | ServerLastStatus::Optional => {
+                    return Err(anyhow!("PagerDuty can not trigger on status {:?}", status))
+                }
+                status => status,
+            };
+            if !SEVERITIES.contains(&level.as_str()) {
+                return Err(anyhow!(
+                    "Invalid PagerDuty severity {:?}, expect one of {:?}",
+                    level,
+                    SEVERITIES
+                ));
+            }
+            severity.insert(status, level.clone());
+        }
+        Ok(Self {
+            routing_key: cfg.routing_key().to_string(),
+            url: cfg.url().to_string(),
+            severity,
+            client: reqwest::ClientBuilder::new()
+                .timeout(Duration::from_secs(10))
+                .build()?,
+        })
+    }
+
+    fn build_event(&self, change: &StatusChange) -> Option<serde_json::Value> {
+        if change.new_status() == ServerLastStatus::Optional {
+            return Some(json!({
+                "routing_key": self.routing_key,
+                "event_action": "resolve",
+                "dedup_key": change.uuid(),
+            }));
+        }
+        let severity = self.severity.get(&change.new_status())?;
+        let timestamp = NaiveDateTime::from_timestamp_opt(change.timestamp() as i64, 0)
+            .map(|time| time.format("%Y-%m-%dT%H:%M:%SZ").to_string());
+        Some(json!({
+            "routing_key": self.routing_key,
+            "event_action": "trigger",
+            "dedup_key": change.uuid(),
+            "payload": {
+                "summary": format!("{} is {}", change.name(), change.new_status().label()),
+                "source": change.uuid(),
+                "severity": severity,
+                "timestamp": timestamp,
+                "component": change.name(),
+                "custom_details": {
+                    "old_status": change.old_status().to_string(),
+                    "new_status": change.new_status().to_string(),
+                },
+            },
+        }))
+    }
+}
+
+#[async_trait::async_trait]
+impl UpstreamTrait for PagerDutyUpstream {
+    fn name(&self) -> &str {
+        "pagerduty"
+    }
+
+    async fn set_component_status(&self, change: &StatusChange) -> anyhow::Result<Pushed> {
+        if change.old_status() == change.new_status() {
+            return Ok(Pushed::Skipped);
+        }
+        let event = match self.build_event(change) {
+            Some(event) => event,
+            None => return Ok(Pushed::Skipped),
+        };
+        self.client
+            .post(&self.url)
+            .json(&event)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(Pushed::Sent)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{change, component, mock_server, received, TIMESTAMP};
+    use axum::http::StatusCode;
+
+    fn pagerduty(extra: &str) -> anyhow::Result<PagerDutyUpstream> {
+        let cfg: configure::PagerDutyUpstream =
+            toml::from_str(&format!("routing_key = \"key\"\n{}", extra)).unwrap();
+        PagerDutyUpstream::from_configure(&cfg)
+    }
+
+    #[test]
+    fn test_build_event() {
+        let pagerduty =
+            pagerduty("[severity]\nmajor_outage = \"critical\"\npartial_outage = \"warning\"")
+                .unwrap();
+
+        let event = pagerduty
+            .build_event(&change(
+                ServerLastStatus::Optional,
+                ServerLastStatus::Outage,
+            ))
+            .unwrap();
+        assert_eq!(event["event_action"], "trigger");
+        assert_eq!(event["dedup_key"], "a");
+        assert_eq!(event["routing_key"], "key");
+        assert_eq!(event["payload"]["severity"], "critical");
+        assert_eq!(event["payload"]["timestamp"], "2020-09-13T12:26:40Z");
+
+        let event = pagerduty
+            .build_event(&change(
+                ServerLastStatus::Outage,
+                ServerLastStatus::PartialOutage,
+            ))
+            .unwrap();
+        assert_eq!(event["event_action"], "trigger");
+        assert_eq!(event["payload"]["severity"], "warning");
+
+        let event = pagerduty
+            .build_event(&change(
+                ServerLastStatus::Outage,
+                ServerLastStatus::Optional,
+            ))
+            .unwrap();
+        assert_eq!(event["event_action"], "resolve");
+        assert_eq!(event["dedup_key"], "a");
+        assert!(event.get("payload").is_none());
+
+        assert!(pagerduty
+            .build_event(&change(
+                ServerLastStatus::Optional,
+                ServerLastStatus::DegradedPerformance
+            ))
+            .is_none());
+    }
+
+    #[test]
+    fn test_severity_validation() {
+        assert!(pagerduty("").is_ok());
+        assert!(pagerduty("[severity]\nmajor_outage = \"fatal\"").is_err());
+        assert!(pagerduty("[severity]\noperational = \"info\"").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_post() {
+        let (url, log) = mock_server(|received| {
+            // Event with a dedup key of `b` stands for a rejected event.
+            if received.json()["dedup_key"] == "b" {
+                (
+                    StatusCode::BAD_REQUEST,
+                    r#"{"status":"invalid event"}"#.to_string(),
+                )
+            } else {
+                (StatusCode::ACCEPTED, r#"{"status":"success"}"#.to_string())
+            }
+        });
+        let pagerduty = pagerduty(&format!("url = \"{}/v2/enqueue\"", url)).unwrap();
+
+        let down = change(ServerLastStatus::Optional, ServerLastStatus::Outage);
+        let up = change(ServerLastStatus::Outage, ServerLastStatus::Optional);
+        for change in [&down, &up] {
+            assert_eq!(
+                pagerduty.set_component_status(change).await.unwrap(),
+                Pushed::Sent
+            );
+        }
+        // Unchanged status and status without severity send nothing.
+        for (old, new) in [
+            (ServerLastStatus::Outage, ServerLastStatus::Outage),
+            (ServerLastStatus::Optional, ServerLastStatus::PartialOutage),
+        ] {
+            assert_eq!(
+                pagerduty
+                    .set_component_status(&change(old, new))
+                    .await
+                    .unwrap(),
+                Pushed::Skipped
+            );
+        }
+
+        let received = received(&log);
+        assert_eq!(received.len(), 2);
+        for (request, change) in received.iter().zip([&down, &up]) {
+            assert_eq!(request.method, "POST");
+            assert_eq!(request.uri.path(), "/v2/enqueue");
+            assert_eq!(request.header("content-type"), "application/json");
+            assert_eq!(request.json(), pagerduty.build_event(change).unwrap());
+        }
+
+        let rejected = StatusChange::new(
+            &component("b", "DB"),
+            ServerLastStatus::Optional,
+            ServerLastStatus::Outage,
+            TIMESTAMP,
+        );
+        assert!(pagerduty.set_component_status(&rejected).await.is_err());
+    }
+}
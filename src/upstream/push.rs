@@ -0,0 +1,292 @@
+/*
+ ** Copyright (C) 2022 KunoiSayami
+ **
+ ** This program is free software: you can redistribute it and/or modify
+ ** it under the terms of the GNU Affero General Public License as published by
+ ** the Free Software Foundation, either version 3 of the License, or
+ ** any later version.
+ **
+ ** This program is distributed in the hope that it will be useful,
+ ** but WITHOUT ANY WARRANTY; without even the implied warranty of
+ ** MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ ** GNU Affero General Public License for more details.
+ **
+ ** You should have received a copy of the GNU Affero General Public License
+ ** along with this program. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use super::notify::{should_notify, Notification};
+use crate::configure::Configure;
+use crate::datastructures::{Pushed, ServerLastStatus, StatusChange, UpstreamTrait};
+use anyhow::anyhow;
+use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION};
+use reqwest::Client;
+use serde_json::json;
+use sqlx::SqliteConnection;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+#[derive(Copy, Clone, Debug)]
+pub enum Priority {
+    Low,
+    Default,
+    High,
+    Urgent,
+}
+
+impl From<ServerLastStatus> for Priority {
+    fn from(status: ServerLastStatus) -> Self {
+        match status {
+            ServerLastStatus::Outage => Priority::Urgent,
+            ServerLastStatus::PartialOutage => Priority::High,
+            ServerLastStatus::Optional | ServerLastStatus::Unknown => Priority::Default,
+            ServerLastStatus::DegradedPerformance => Priority::Low,
+        }
+    }
+}
+
+impl Priority {
+    /// ntfy priority, 1 (min) to 5 (urgent).
+    pub fn ntfy(&self) -> u8 {
+        match self {
+            Priority::Low => 2,
+            Priority::Default => 3,
+            Priority::High => 4,
+            Priority::Urgent => 5,
+        }
+    }
+
+    /// Gotify priority, 0 to 10, clients alert loudly from 8.
+    pub fn gotify(&self) -> u8 {
+        match self {
+            Priority::Low => 2,
+            Priority::Default => 5,
+            Priority::High => 8,
+            Priority::Urgent => 10,
+        }
+    }
+}
+
+fn build_client(map: HeaderMap) -> anyhow::Result<Client> {
+    Ok(reqwest::ClientBuilder::new()
+        .default_headers(map)
+        .timeout(Duration::from_secs(10))
+        .build()?)
+}
+
+fn build_message(change: &StatusChange) -> String {
+    format!(
+        "{} ({}) is now {}",
+        change.name(),
+        change.uuid(),
+        change.new_status().label()
+    )
+}
+
+/// Publish status changes to a ntfy topic.
+pub struct NtfyUpstream {
+    url: String,
+    topic: String,
+    client: Client,
+    conn: Arc<Mutex<SqliteConnection>>,
+}
+
+impl NtfyUpstream {
+    pub fn from_configure(
+        cfg: &Configure,
+        conn: Arc<Mutex<SqliteConnection>>,
+    ) -> anyhow::Result<Option<Self>> {
+        let cfg = cfg.ntfy();
+        if !cfg.enabled() {
+            return Ok(None);
+        }
+        if cfg.topic().is_empty() {
+            return Err(anyhow!("ntfy topic is empty"));
+        }
+        let mut map = HeaderMap::new();
+        if !cfg.token().is_empty() {
+            map.insert(
+                AUTHORIZATION,
+                HeaderValue::from_str(&format!("Bearer {}", cfg.token()))
+                    .map_err(|e| anyhow!("Invalid ntfy token: {:?}", e))?,
+            );
+        }
+        Ok(Some(Self {
+            url: cfg.url().trim_end_matches('/').to_string(),
+            topic: cfg.topic().to_string(),
+            client: build_client(map)?,
+            conn,
+        }))
+    }
+}
+
+#[async_trait::async_trait]
+impl UpstreamTrait for NtfyUpstream {
+    fn name(&self) -> &str {
+        "ntfy"
+    }
+
+    async fn set_component_status(&self, change: &StatusChange) -> anyhow::Result<Pushed> {
+        if !should_notify(&[], change) {
+            return Ok(Pushed::Skipped);
+        }
+        let notification = Notification::new(&self.conn, change).await;
+        // JSON publish keeps non-ASCII title out of the headers.
+        self.client
+            .post(&self.url)
+            .json(&json!({
+                "topic": self.topic,
+                "title": notification.summary(),
+                "message": build_message(change),
+                "priority": Priority::from(change.new_status()).ntfy(),
+            }))
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(Pushed::Sent)
+    }
+}
+
+/// Push status changes as messages of a Gotify application.
+pub struct GotifyUpstream {
+    url: String,
+    client: Client,
+    conn: Arc<Mutex<SqliteConnection>>,
+}
+
+impl GotifyUpstream {
+    pub fn from_configure(
+        cfg: &Configure,
+        conn: Arc<Mutex<SqliteConnection>>,
+    ) -> anyhow::Result<Option<Self>> {
+        let cfg = cfg.gotify();
+        if !cfg.enabled() {
+            return Ok(None);
+        }
+        if cfg.url().is_empty() || cfg.token().is_empty() {
+            return Err(anyhow!("Gotify url or token is empty"));
+        }
+        let mut map = HeaderMap::new();
+        map.insert(
+            "X-Gotify-Key",
+            HeaderValue::from_str(cfg.token())
+                .map_err(|e| anyhow!("Invalid Gotify token: {:?}", e))?,
+        );
+        Ok(Some(Self {
+            url: format!("{}/message", cfg.url().trim_end_matches('/')),
+            client: build_client(map)?,
+            conn,
+        }))
+    }
+}
+
+#[async_trait::async_trait]
+impl UpstreamTrait for GotifyUpstream {
+    fn name(&self) -> &str {
+        "gotify"
+    }
+
+    async fn set_component_status(&self, change: &StatusChange) -> anyhow::Result<Pushed> {
+        if !should_notify(&[], change) {
+            return Ok(Pushed::Skipped);
+        }
+        let notification = Notification::new(&self.conn, change).await;
+        self.client
+            .post(&self.url)
+            .json(&json!({
+                "title": notification.summary(),
+                "message": build_message(change),
+                "priority": Priority::from(change.new_status()).gotify(),
+            }))
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(Pushed::Sent)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{change, configure, memory_database, mock_ok, received};
+
+    fn priorities(status: ServerLastStatus) -> (u8, u8) {
+        let priority = Priority::from(status);
+        (priority.ntfy(), priority.gotify())
+    }
+
+    #[test]
+    fn test_priority() {
+        assert_eq!(priorities(ServerLastStatus::Outage), (5, 10));
+        assert_eq!(priorities(ServerLastStatus::PartialOutage), (4, 8));
+        assert_eq!(priorities(ServerLastStatus::Optional), (3, 5));
+        assert_eq!(priorities(ServerLastStatus::Unknown), (3, 5));
+        assert_eq!(priorities(ServerLastStatus::DegradedPerformance), (2, 2));
+    }
+
+    #[tokio::test]
+    async fn test_ntfy() {
+        let (url, log) = mock_ok();
+        let conn = Arc::new(Mutex::new(memory_database().await));
+        let ntfy = NtfyUpstream::from_configure(
+            &configure(&format!(
+                "[ntfy]\nenabled = true\nurl = \"{}/\"\ntopic = \"status\"\ntoken = \"tk\"",
+                url
+            )),
+            conn,
+        )
+        .unwrap()
+        .unwrap();
+        ntfy.set_component_status(&change(
+            ServerLastStatus::Optional,
+            ServerLastStatus::Outage,
+        ))
+        .await
+        .unwrap();
+        // Unchanged status is not notified.
+        ntfy.set_component_status(&change(ServerLastStatus::Outage, ServerLastStatus::Outage))
+            .await
+            .unwrap();
+
+        let received = received(&log);
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].method, "POST");
+        assert_eq!(received[0].uri.path(), "/");
+        assert_eq!(received[0].header("authorization"), "Bearer tk");
+        let body = received[0].json();
+        assert_eq!(body["topic"], "status");
+        assert_eq!(body["priority"], 5);
+        assert_eq!(body["message"], "Web (a) is now major outage");
+    }
+
+    #[tokio::test]
+    async fn test_gotify() {
+        let (url, log) = mock_ok();
+        let conn = Arc::new(Mutex::new(memory_database().await));
+        let gotify = GotifyUpstream::from_configure(
+            &configure(&format!(
+                "[gotify]\nenabled = true\nurl = \"{}\"\ntoken = \"app\"",
+                url
+            )),
+            conn,
+        )
+        .unwrap()
+        .unwrap();
+        gotify
+            .set_component_status(&change(
+                ServerLastStatus::Outage,
+                ServerLastStatus::PartialOutage,
+            ))
+            .await
+            .unwrap();
+
+        let received = received(&log);
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].uri.path(), "/message");
+        assert_eq!(received[0].header("x-gotify-key"), "app");
+        let body = received[0].json();
+        assert_eq!(body["priority"], 8);
+        assert_eq!(body["message"], "Web (a) is now partial outage");
+    }
+}
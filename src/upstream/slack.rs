@@ -0,0 +1,131 @@
+/*
+ ** Copyright (C) 2022 KunoiSayami
+ **
+ ** This program is free software: you can redistribute it and/or modify
+ ** it under the terms of the GNU Affero General Public License as published by
+ ** the Free Software Foundation, either version 3 of the License, or
+ ** any later version.
+ **
+ ** This program is distributed in the hope that it will be useful,
+ ** but WITHOUT ANY WARRANTY; without even the implied warranty of
+ ** MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ ** GNU Affero General Public License for more details.
+ **
+ ** You should have received a copy of the GNU Affero General Public License
+ ** along with this program. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use super::notify::{should_notify, Notification};
+use crate::configure::ChatNotifier;
+use crate::datastructures::{Pushed, StatusChange, UpstreamTrait};
+use reqwest::Client;
+use serde_json::json;
+use sqlx::SqliteConnection;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+/// Post status changes to a Slack incoming webhook.
+pub struct SlackNotifier {
+    name: String,
+    url: String,
+    components: Vec<String>,
+    client: Client,
+    conn: Arc<Mutex<SqliteConnection>>,
+}
+
+impl SlackNotifier {
+    pub fn from_configure(
+        cfg: &ChatNotifier,
+        conn: Arc<Mutex<SqliteConnection>>,
+    ) -> anyhow::Result<Self> {
+        Ok(Self {
+            name: cfg.name().unwrap_or("slack").to_string(),
+            url: cfg.url().to_string(),
+            components: cfg.components().clone(),
+            client: reqwest::ClientBuilder::new()
+                .timeout(Duration::from_secs(10))
+                .build()?,
+            conn,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl UpstreamTrait for SlackNotifier {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn set_component_status(&self, change: &StatusChange) -> anyhow::Result<Pushed> {
+        if !should_notify(&self.components, change) {
+            return Ok(Pushed::Skipped);
+        }
+        let notification = Notification::new(&self.conn, change).await;
+        let summary = notification.summary();
+        self.client
+            .post(&self.url)
+            .json(&json!({
+                "text": summary,
+                "attachments": [{
+                    "fallback": summary,
+                    "color": notification.colour(),
+                    "text": format!("`{}` is now *{}*", change.uuid(), change.new_status().label()),
+                    "ts": change.timestamp(),
+                }],
+            }))
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(Pushed::Sent)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datastructures::ServerLastStatus;
+    use crate::test_util::{change, memory_database, mock_ok, received, TIMESTAMP};
+
+    #[tokio::test]
+    async fn test_post() {
+        let (url, log) = mock_ok();
+        let cfg: ChatNotifier = toml::from_str(&format!(
+            "url = \"{}/services/x\"\ncomponents = [\"a\"]",
+            url
+        ))
+        .unwrap();
+        let slack =
+            SlackNotifier::from_configure(&cfg, Arc::new(Mutex::new(memory_database().await)))
+                .unwrap();
+        assert_eq!(slack.name(), "slack");
+
+        let down = change(ServerLastStatus::Optional, ServerLastStatus::Outage);
+        assert_eq!(
+            slack.set_component_status(&down).await.unwrap(),
+            Pushed::Sent
+        );
+        let unchanged = change(ServerLastStatus::Outage, ServerLastStatus::Outage);
+        assert_eq!(
+            slack.set_component_status(&unchanged).await.unwrap(),
+            Pushed::Skipped
+        );
+
+        let received = received(&log);
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].method, "POST");
+        assert_eq!(received[0].uri.path(), "/services/x");
+        assert_eq!(
+            received[0].json(),
+            json!({
+                "text": "Web operational → major outage",
+                "attachments": [{
+                    "fallback": "Web operational → major outage",
+                    "color": "#e74c3c",
+                    "text": "`a` is now *major outage*",
+                    "ts": TIMESTAMP,
+                }],
+            })
+        );
+    }
+}
@@ -0,0 +1,192 @@
+/*
+ ** Copyright (C) 2022 KunoiSayami
+ **
+ ** This program is free software: you can redistribute it and/or modify
+ ** it under the terms of the GNU Affero General Public License as published by
+ ** the Free Software Foundation, either version 3 of the License, or
+ ** any later version.
+ **
+ ** This program is distributed in the hope that it will be useful,
+ ** but WITHOUT ANY WARRANTY; without even the implied warranty of
+ ** MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ ** GNU Affero General Public License for more details.
+ **
+ ** You should have received a copy of the GNU Affero General Public License
+ ** along with this program. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use super::notify::{should_notify, Notification};
+use crate::configure;
+use crate::datastructures::{Pushed, StatusChange, UpstreamTrait};
+use anyhow::anyhow;
+#[cfg(any(feature = "env_logger", feature = "log4rs"))]
+use log::warn;
+use reqwest::Client;
+use serde_json::json;
+#[cfg(feature = "spdlog-rs")]
+use spdlog::prelude::*;
+use sqlx::SqliteConnection;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+/// Send status changes to Telegram chats through the Bot API `sendMessage` method.
+pub struct TelegramNotifier {
+    url: String,
+    chat_ids: Vec<String>,
+    components: HashMap<String, Vec<String>>,
+    client: Client,
+    conn: Arc<Mutex<SqliteConnection>>,
+}
+
+impl TelegramNotifier {
+    pub fn from_configure(
+        cfg: &configure::TelegramNotifier,
+        conn: Arc<Mutex<SqliteConnection>>,
+    ) -> anyhow::Result<Self> {
+        Ok(Self {
+            url: format!(
+                "{}/bot{}/sendMessage",
+                cfg.api_url().trim_end_matches('/'),
+                cfg.bot_token()
+            ),
+            chat_ids: cfg.chat_ids().clone(),
+            components: cfg.components().clone(),
+            client: reqwest::ClientBuilder::new()
+                .timeout(Duration::from_secs(10))
+                .build()?,
+            conn,
+        })
+    }
+
+    fn chats(&self, uuid: &str) -> &Vec<String> {
+        self.components.get(uuid).unwrap_or(&self.chat_ids)
+    }
+}
+
+#[async_trait::async_trait]
+impl UpstreamTrait for TelegramNotifier {
+    fn name(&self) -> &str {
+        "telegram"
+    }
+
+    /// Partial delivery counts as success, retrying would repeat the message in every chat
+    /// already reached, so only chats failing altogether hand the change over to the outbox.
+    async fn set_component_status(&self, change: &StatusChange) -> anyhow::Result<Pushed> {
+        let chats = self.chats(change.uuid());
+        if chats.is_empty() || !should_notify(&[], change) {
+            return Ok(Pushed::Skipped);
+        }
+        let text = Notification::new(&self.conn, change).await.summary();
+        let mut errors = Vec::new();
+        for chat_id in chats {
+            // Bot token is part of the url, keep it out of the error message.
+            if let Err(e) = self
+                .client
+                .post(&self.url)
+                .json(&json!({ "chat_id": chat_id, "text": text }))
+                .send()
+                .await
+                .and_then(|response| response.error_for_status())
+            {
+                errors.push(format!("chat {}: {}", chat_id, e.without_url()));
+            }
+        }
+        if errors.len() == chats.len() {
+            return Err(anyhow!("Send message error: {}", errors.join(", ")));
+        }
+        for e in errors {
+            warn!("Send status of {} to Telegram {} failed", change.uuid(), e);
+        }
+        Ok(Pushed::Sent)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datastructures::ServerLastStatus;
+    use crate::test_util::{
+        change, component, memory_database, mock_server, received, ReceivedLog, TIMESTAMP,
+    };
+    use axum::http::StatusCode;
+
+    /// Bot API rejecting chats in `failing`.
+    async fn telegram(failing: &'static [&'static str]) -> (TelegramNotifier, ReceivedLog) {
+        let (url, log) = mock_server(move |received| {
+            if failing.contains(&received.json()["chat_id"].as_str().unwrap()) {
+                (StatusCode::BAD_REQUEST, r#"{"ok":false}"#.to_string())
+            } else {
+                (StatusCode::OK, r#"{"ok":true}"#.to_string())
+            }
+        });
+        let cfg: configure::TelegramNotifier = toml::from_str(&format!(
+            "bot_token = \"123:abc\"\napi_url = \"{}/\"\nchat_ids = [\"1\", \"2\"]\n[components]\nb = [\"3\"]",
+            url
+        ))
+        .unwrap();
+        let conn = Arc::new(Mutex::new(memory_database().await));
+        (TelegramNotifier::from_configure(&cfg, conn).unwrap(), log)
+    }
+
+    fn chats(log: &ReceivedLog) -> Vec<String> {
+        received(log)
+            .iter()
+            .map(|received| received.json()["chat_id"].as_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_routing() {
+        let (telegram, log) = telegram(&[]).await;
+        let down = change(ServerLastStatus::Optional, ServerLastStatus::Outage);
+        assert_eq!(
+            telegram.set_component_status(&down).await.unwrap(),
+            Pushed::Sent
+        );
+
+        // Component `a` has no entry of its own and falls back to chat_ids.
+        assert_eq!(chats(&log), ["1", "2"]);
+        let received = received(&log);
+        assert_eq!(received[0].method, "POST");
+        assert_eq!(received[0].uri.path(), "/bot123:abc/sendMessage");
+        assert_eq!(received[0].json()["text"], "Web operational → major outage");
+
+        let routed = StatusChange::new(
+            &component("b", "DB"),
+            ServerLastStatus::Optional,
+            ServerLastStatus::Outage,
+            TIMESTAMP,
+        );
+        telegram.set_component_status(&routed).await.unwrap();
+        assert_eq!(chats(&log), ["1", "2", "3"]);
+    }
+
+    #[tokio::test]
+    async fn test_partial_failure() {
+        let (telegram, log) = telegram(&["1"]).await;
+        let down = change(ServerLastStatus::Optional, ServerLastStatus::Outage);
+        // Chat 2 got the message, retrying would send it there again.
+        assert_eq!(
+            telegram.set_component_status(&down).await.unwrap(),
+            Pushed::Sent
+        );
+        assert_eq!(chats(&log), ["1", "2"]);
+    }
+
+    #[tokio::test]
+    async fn test_total_failure() {
+        let (telegram, log) = telegram(&["1", "2"]).await;
+        let down = change(ServerLastStatus::Optional, ServerLastStatus::Outage);
+        let e = telegram
+            .set_component_status(&down)
+            .await
+            .unwrap_err()
+            .to_string();
+        assert_eq!(chats(&log), ["1", "2"]);
+        assert!(e.contains("chat 1: ") && e.contains("chat 2: "), "{}", e);
+        // Bot token stays out of the error.
+        assert!(!e.contains("123:abc"), "{}", e);
+    }
+}
@@ -0,0 +1,168 @@
+/*
+ ** Copyright (C) 2022 KunoiSayami
+ **
+ ** This program is free software: you can redistribute it and/or modify
+ ** it under the terms of the GNU Affero General Public License as published by
+ ** the Free Software Foundation, either version 3 of the License, or
+ ** any later version.
+ **
+ ** This program is distributed in the hope that it will be useful,
+ ** but WITHOUT ANY WARRANTY; without even the implied warranty of
+ ** MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ ** GNU Affero General Public License for more details.
+ **
+ ** You should have received a copy of the GNU Affero General Public License
+ ** along with this program. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::configure;
+use crate::datastructures::{Pushed, ServerLastStatus, StatusChange, UpstreamTrait};
+use anyhow::anyhow;
+use reqwest::Client;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::time::Duration;
+
+#[derive(Deserialize)]
+struct PushResponse {
+    ok: bool,
+    msg: Option<String>,
+}
+
+/// Feed Uptime Kuma push monitors, one push token per component.
+pub struct UptimeKumaUpstream {
+    url: String,
+    components: HashMap<String, String>,
+    client: Client,
+}
+
+impl UptimeKumaUpstream {
+    pub fn from_configure(cfg: &configure::UptimeKumaUpstream) -> anyhow::Result<Self> {
+        Ok(Self {
+            url: cfg.url().trim_end_matches('/').to_string(),
+            components: cfg.components().clone(),
+            client: reqwest::ClientBuilder::new()
+                .timeout(Duration::from_secs(10))
+                .build()?,
+        })
+    }
+
+    pub fn build_request_url(&self, token: &str) -> String {
+        format!("{}/api/push/{}", self.url, token)
+    }
+}
+
+#[async_trait::async_trait]
+impl UpstreamTrait for UptimeKumaUpstream {
+    fn name(&self) -> &str {
+        "uptime_kuma"
+    }
+
+    async fn set_component_status(&self, change: &StatusChange) -> anyhow::Result<Pushed> {
+        let token = match self.components.get(change.uuid()) {
+            Some(token) => token,
+            None => return Ok(Pushed::Skipped),
+        };
+        // Kuma only knows up and down, a degraded component still serves requests.
+        let status = match change.new_status() {
+            ServerLastStatus::Optional | ServerLastStatus::DegradedPerformance => "up",
+            ServerLastStatus::PartialOutage | ServerLastStatus::Outage => "down",
+            // Nothing reported yet, let the monitor heartbeat decide.
+            ServerLastStatus::Unknown => return Ok(Pushed::Skipped),
+        };
+        // Push token is part of the url, keep it out of the error message.
+        let response = self
+            .client
+            .get(self.build_request_url(token))
+            .query(&[
+                ("status", status),
+                ("msg", &change.new_status().to_string()),
+            ])
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| anyhow!("Push {} error: {}", change.uuid(), e.without_url()))?
+            .json::<PushResponse>()
+            .await
+            .map_err(|e| anyhow!("Decode push response error: {}", e.without_url()))?;
+        if !response.ok {
+            return Err(anyhow!(
+                "Uptime Kuma rejected push of {}: {}",
+                change.uuid(),
+                response.msg.unwrap_or_default()
+            ));
+        }
+        Ok(Pushed::Sent)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{change, component, mock_server, received, TIMESTAMP};
+    use axum::http::StatusCode;
+
+    fn uptime_kuma(url: &str) -> UptimeKumaUpstream {
+        let cfg: configure::UptimeKumaUpstream =
+            toml::from_str(&format!("url = \"{}/\"\n[components]\na = \"token\"", url)).unwrap();
+        UptimeKumaUpstream::from_configure(&cfg).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_push() {
+        let (url, log) = mock_server(|_| (StatusCode::OK, r#"{"ok":true}"#.to_string()));
+        let uptime_kuma = uptime_kuma(&url);
+
+        for (status, expected) in [
+            (ServerLastStatus::Outage, "down"),
+            (ServerLastStatus::PartialOutage, "down"),
+            (ServerLastStatus::DegradedPerformance, "up"),
+            (ServerLastStatus::Optional, "up"),
+        ] {
+            uptime_kuma
+                .set_component_status(&change(ServerLastStatus::Unknown, status))
+                .await
+                .unwrap();
+            let request = received(&log).pop().unwrap();
+            assert_eq!(request.uri.path(), "/api/push/token");
+            assert_eq!(
+                request.uri.query().unwrap(),
+                format!("status={}&msg={}", expected, status)
+            );
+        }
+
+        // Components without a push token are not routed to Uptime Kuma.
+        let other = StatusChange::new(
+            &component("b", "Other"),
+            ServerLastStatus::Optional,
+            ServerLastStatus::Outage,
+            TIMESTAMP,
+        );
+        uptime_kuma.set_component_status(&other).await.unwrap();
+        uptime_kuma
+            .set_component_status(&change(
+                ServerLastStatus::Optional,
+                ServerLastStatus::Unknown,
+            ))
+            .await
+            .unwrap();
+        assert_eq!(received(&log).len(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_rejected_push() {
+        let (url, _) = mock_server(|_| {
+            (
+                StatusCode::OK,
+                r#"{"ok":false,"msg":"Monitor not found"}"#.to_string(),
+            )
+        });
+        let ret = uptime_kuma(&url)
+            .set_component_status(&change(
+                ServerLastStatus::Optional,
+                ServerLastStatus::Outage,
+            ))
+            .await;
+        assert!(ret.unwrap_err().to_string().contains("Monitor not found"));
+    }
+}
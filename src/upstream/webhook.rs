@@ -0,0 +1,422 @@
+/*
+ ** Copyright (C) 2022 KunoiSayami
+ **
+ ** This program is free software: you can redistribute it and/or modify
+ ** it under the terms of the GNU Affero General Public License as published by
+ ** the Free Software Foundation, either version 3 of the License, or
+ ** any later version.
+ **
+ ** This program is distributed in the hope that it will be useful,
+ ** but WITHOUT ANY WARRANTY; without even the implied warranty of
+ ** MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ ** GNU Affero General Public License for more details.
+ **
+ ** You should have received a copy of the GNU Affero General Public License
+ ** along with this program. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::configure;
+use crate::database::get_current_timestamp;
+use crate::datastructures::{Pushed, StatusChange, UpstreamTrait};
+use anyhow::anyhow;
+use hmac::{Hmac, Mac};
+#[cfg(any(feature = "env_logger", feature = "log4rs"))]
+use log::error;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_TYPE};
+use reqwest::{Client, Method, StatusCode};
+use serde_json::json;
+use sha2::Sha256;
+#[cfg(feature = "spdlog-rs")]
+use spdlog::prelude::*;
+use sqlx::SqliteConnection;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+pub const DELIVERY_HEADER: &str = "X-Webhook-Delivery";
+pub const TIMESTAMP_HEADER: &str = "X-Webhook-Timestamp";
+pub const SIGNATURE_HEADER: &str = "X-Webhook-Signature";
+const DAY: i64 = 24 * 3600;
+
+/// Send every status change to an arbitrary HTTP endpoint.
+///
+/// Each attempt is recorded in the `webhook_deliveries` table.
+pub struct WebhookUpstream {
+    name: String,
+    url: String,
+    method: Method,
+    body: Option<String>,
+    secret: Option<String>,
+    retention_days: u64,
+    client: Client,
+    conn: Arc<Mutex<SqliteConnection>>,
+}
+
+impl WebhookUpstream {
+    pub fn from_configure(
+        cfg: &configure::WebhookUpstream,
+        conn: Arc<Mutex<SqliteConnection>>,
+    ) -> anyhow::Result<Self> {
+        let method = Method::from_bytes(cfg.method().to_uppercase().as_bytes()).map_err(|_| {
+            anyhow!(
+                "Invalid method {:?} of webhook {}",
+                cfg.method(),
+                cfg.name()
+            )
+        })?;
+
+        let mut map = HeaderMap::new();
+        map.insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+        for (key, value) in cfg.headers() {
+            map.insert(
+                HeaderName::from_bytes(key.as_bytes())
+                    .map_err(|e| anyhow!("Invalid header name {:?}: {:?}", key, e))?,
+                HeaderValue::from_str(value)
+                    .map_err(|e| anyhow!("Invalid header value of {:?}: {:?}", key, e))?,
+            );
+        }
+
+        Ok(Self {
+            name: cfg.name().to_string(),
+            url: cfg.url().to_string(),
+            method,
+            body: cfg.body().map(|s| s.to_string()),
+            secret: cfg.secret().map(|s| s.to_string()),
+            retention_days: cfg.retention_days(),
+            client: reqwest::ClientBuilder::new()
+                .default_headers(map)
+                .timeout(Duration::from_secs(10))
+                .build()?,
+            conn,
+        })
+    }
+
+    /// Same change always gets the same id, so receivers can drop deliveries retried
+    /// from the outbox.
+    pub fn delivery_id(&self, change: &StatusChange) -> String {
+        Uuid::new_v5(
+            &Uuid::NAMESPACE_OID,
+            format!(
+                "{}/{}/{}/{}/{}",
+                self.name,
+                change.uuid(),
+                change.old_status(),
+                change.new_status(),
+                change.timestamp()
+            )
+            .as_bytes(),
+        )
+        .to_string()
+    }
+
+    /// Hex encoded HMAC-SHA256 of `<timestamp>.<body>`.
+    pub fn sign(secret: &str, timestamp: u64, body: &str) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+            .expect("HMAC can take key of any size");
+        mac.update(timestamp.to_string().as_bytes());
+        mac.update(b".");
+        mac.update(body.as_bytes());
+        hex::encode(mac.finalize().into_bytes())
+    }
+
+    async fn send(&self, delivery_id: &str, body: &str) -> Result<StatusCode, reqwest::Error> {
+        let timestamp = get_current_timestamp();
+        let mut request = self
+            .client
+            .request(self.method.clone(), &self.url)
+            .header(DELIVERY_HEADER, delivery_id)
+            .header(TIMESTAMP_HEADER, timestamp);
+        if let Some(ref secret) = self.secret {
+            request = request.header(
+                SIGNATURE_HEADER,
+                format!("sha256={}", Self::sign(secret, timestamp, body)),
+            );
+        }
+        Ok(request.body(body.to_string()).send().await?.status())
+    }
+
+    async fn record(
+        &self,
+        delivery_id: &str,
+        change: &StatusChange,
+        status_code: Option<StatusCode>,
+        error: Option<String>,
+    ) -> anyhow::Result<()> {
+        let mut conn = self.conn.lock().await;
+        sqlx::query(
+            r#"INSERT INTO "webhook_deliveries"
+                ("delivery_id", "upstream", "uuid", "new_status", "attempt", "status_code",
+                "error", "timestamp")
+                SELECT ?, ?, ?, ?, COUNT(*) + 1, ?, ?, ? FROM "webhook_deliveries"
+                WHERE "delivery_id" = ?"#,
+        )
+        .bind(delivery_id)
+        .bind(&self.name)
+        .bind(change.uuid())
+        .bind(change.new_status().to_string())
+        .bind(status_code.map(|code| code.as_u16()))
+        .bind(error)
+        .bind(get_current_timestamp() as i64)
+        .bind(delivery_id)
+        .execute(&mut *conn)
+        .await
+        .map_err(|e| anyhow!("Record delivery {} error: {:?}", delivery_id, e))?;
+
+        sqlx::query(r#"DELETE FROM "webhook_deliveries" WHERE "upstream" = ? AND "timestamp" < ?"#)
+            .bind(&self.name)
+            .bind(get_current_timestamp() as i64 - self.retention_days as i64 * DAY)
+            .execute(&mut *conn)
+            .await
+            .map_err(|e| anyhow!("Prune deliveries of {} error: {:?}", self.name, e))?;
+        Ok(())
+    }
+
+    pub fn build_body(&self, change: &StatusChange) -> String {
+        match self.body {
+            // Values are JSON escaped, template should put string placeholders inside quotes.
+            Some(ref template) => change.render(template, |value| {
+                let quoted = serde_json::to_string(value).unwrap();
+                quoted[1..quoted.len() - 1].to_string()
+            }),
+            None => json!({
+                "uuid": change.uuid(),
+                "name": change.name(),
+                "old_status": change.old_status().to_string(),
+                "new_status": change.new_status().to_string(),
+                "timestamp": change.timestamp(),
+            })
+            .to_string(),
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl UpstreamTrait for WebhookUpstream {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Make a single attempt, a failed delivery is retried by the outbox with the same id.
+    async fn set_component_status(&self, change: &StatusChange) -> anyhow::Result<Pushed> {
+        // Periodic resync repeats unchanged status, which is not an event for receivers.
+        if change.old_status() == change.new_status() {
+            return Ok(Pushed::Skipped);
+        }
+        let body = self.build_body(change);
+        let delivery_id = self.delivery_id(change);
+        let (status_code, ret) = match self.send(&delivery_id, &body).await {
+            Ok(code) if code.is_success() => (Some(code), Ok(Pushed::Sent)),
+            Ok(code) => (Some(code), Err(anyhow!("Webhook responded {}", code))),
+            // The url may carry credentials, keep it out of the recorded error.
+            Err(e) => (
+                None,
+                Err(anyhow!("Send webhook error: {:?}", e.without_url())),
+            ),
+        };
+        self.record(
+            &delivery_id,
+            change,
+            status_code,
+            ret.as_ref().err().map(|e| e.to_string()),
+        )
+        .await
+        .unwrap_or_else(|e| error!("{:?}", e));
+        ret
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::datastructures::ServerLastStatus;
+    use crate::test_util::{
+        change, component, memory_database, mock_ok, mock_server, received, TIMESTAMP,
+    };
+
+    async fn webhook(name: &str, url: &str, extra: &str) -> WebhookUpstream {
+        let cfg: configure::WebhookUpstream = toml::from_str(&format!(
+            "name = \"{}\"\nurl = \"{}/hook\"\n{}",
+            name, url, extra
+        ))
+        .unwrap();
+        WebhookUpstream::from_configure(&cfg, Arc::new(Mutex::new(memory_database().await)))
+            .unwrap()
+    }
+
+    async fn deliveries(webhook: &WebhookUpstream) -> Vec<(String, i64, Option<i64>)> {
+        sqlx::query_as::<_, (String, i64, Option<i64>)>(
+            r#"SELECT "delivery_id", "attempt", "status_code" FROM "webhook_deliveries"
+                ORDER BY "id""#,
+        )
+        .fetch_all(&mut *webhook.conn.lock().await)
+        .await
+        .unwrap()
+    }
+
+    #[test]
+    fn test_sign() {
+        assert_eq!(
+            WebhookUpstream::sign("secret", 1600000000, r#"{"a":1}"#),
+            "4e107d82910257d43758070322323c95b92af39939824d6610e2c9809a43b8d5"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_delivery_id() {
+        let (url, _) = mock_ok();
+        let first = webhook("first", &url, "").await;
+        let second = webhook("second", &url, "").await;
+        let down = change(ServerLastStatus::Optional, ServerLastStatus::Outage);
+        let id = first.delivery_id(&down);
+
+        assert_eq!(id, first.delivery_id(&down.clone()));
+        assert!(Uuid::parse_str(&id).is_ok());
+        assert_ne!(id, second.delivery_id(&down));
+        assert_ne!(
+            id,
+            first.delivery_id(&change(
+                ServerLastStatus::Optional,
+                ServerLastStatus::PartialOutage
+            ))
+        );
+        assert_ne!(
+            id,
+            first.delivery_id(&StatusChange::new(
+                &component("a", "Web"),
+                ServerLastStatus::Optional,
+                ServerLastStatus::Outage,
+                TIMESTAMP + 1
+            ))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_delivery() {
+        let (url, log) = mock_ok();
+        let webhook = webhook(
+            "test",
+            &url,
+            "method = \"put\"\nsecret = \"key\"\nheaders = { \"X-Token\" = \"t\" }",
+        )
+        .await;
+        let down = change(ServerLastStatus::Optional, ServerLastStatus::Outage);
+        webhook.set_component_status(&down).await.unwrap();
+
+        let received = received(&log);
+        assert_eq!(received.len(), 1);
+        let request = &received[0];
+        assert_eq!(request.method, Method::PUT);
+        assert_eq!(request.uri.path(), "/hook");
+        assert_eq!(request.header("x-token"), "t");
+        assert_eq!(request.header("content-type"), "application/json");
+        assert_eq!(request.header(DELIVERY_HEADER), webhook.delivery_id(&down));
+        let timestamp = request.header(TIMESTAMP_HEADER).parse().unwrap();
+        assert_eq!(
+            request.header(SIGNATURE_HEADER),
+            format!(
+                "sha256={}",
+                WebhookUpstream::sign("key", timestamp, &request.body)
+            )
+        );
+        assert_eq!(
+            request.json(),
+            json!({
+                "uuid": "a",
+                "name": "Web",
+                "old_status": "operational",
+                "new_status": "major_outage",
+                "timestamp": TIMESTAMP,
+            })
+        );
+        assert_eq!(
+            deliveries(&webhook).await,
+            vec![(webhook.delivery_id(&down), 1, Some(200))]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_failed_attempts_recorded_and_pruned() {
+        let (url, log) = mock_server(|_| (axum::http::StatusCode::BAD_GATEWAY, String::new()));
+        let webhook = webhook("test", &url, "").await;
+        sqlx::query(
+            r#"INSERT INTO "webhook_deliveries"
+                ("delivery_id", "upstream", "uuid", "new_status", "attempt", "timestamp")
+                VALUES ('old', 'test', 'a', 'operational', 1, 0)"#,
+        )
+        .execute(&mut *webhook.conn.lock().await)
+        .await
+        .unwrap();
+
+        // A single attempt per push, the outbox owns retries.
+        let down = change(ServerLastStatus::Optional, ServerLastStatus::Outage);
+        assert!(webhook.set_component_status(&down).await.is_err());
+        assert_eq!(received(&log).len(), 1);
+        assert!(webhook.set_component_status(&down).await.is_err());
+        assert_eq!(received(&log).len(), 2);
+
+        let id = webhook.delivery_id(&down);
+        assert_eq!(
+            deliveries(&webhook).await,
+            vec![(id.clone(), 1, Some(502)), (id, 2, Some(502))]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_send_error_hides_url() {
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let webhook = webhook("test", &format!("http://127.0.0.1:{}", port), "").await;
+        let webhook = WebhookUpstream {
+            url: format!("{}?token=leaked", webhook.url),
+            ..webhook
+        };
+
+        let down = change(ServerLastStatus::Optional, ServerLastStatus::Outage);
+        let e = webhook.set_component_status(&down).await.unwrap_err();
+        assert!(!format!("{:?}", e).contains("leaked"));
+        let (error,) =
+            sqlx::query_as::<_, (String,)>(r#"SELECT "error" FROM "webhook_deliveries""#)
+                .fetch_one(&mut *webhook.conn.lock().await)
+                .await
+                .unwrap();
+        assert!(error.starts_with("Send webhook error"));
+        assert!(!error.contains("leaked"));
+    }
+
+    #[tokio::test]
+    async fn test_build_body_escape() {
+        let (url, _) = mock_ok();
+        let webhook = webhook(
+            "test",
+            &url,
+            r#"body = '{"name": "{{name}}", "at": {{timestamp}}}'"#,
+        )
+        .await;
+        let body = webhook.build_body(&StatusChange::new(
+            &component("a", "Web \"1\""),
+            ServerLastStatus::Optional,
+            ServerLastStatus::Outage,
+            TIMESTAMP,
+        ));
+        assert_eq!(body, r#"{"name": "Web \"1\"", "at": 1600000000}"#);
+        let value: serde_json::Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(value["name"], "Web \"1\"");
+    }
+
+    #[tokio::test]
+    async fn test_skip_unchanged() {
+        let (url, log) = mock_ok();
+        let webhook = webhook("test", &url, "").await;
+        let unchanged = change(ServerLastStatus::Optional, ServerLastStatus::Optional);
+        assert_eq!(
+            webhook.set_component_status(&unchanged).await.unwrap(),
+            Pushed::Skipped
+        );
+        assert!(received(&log).is_empty());
+        assert!(deliveries(&webhook).await.is_empty());
+    }
+}
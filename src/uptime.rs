@@ -0,0 +1,234 @@
+/*
+ ** Copyright (C) 2022 KunoiSayami
+ **
+ ** This program is free software: you can redistribute it and/or modify
+ ** it under the terms of the GNU Affero General Public License as published by
+ ** the Free Software Foundation, either version 3 of the License, or
+ ** any later version.
+ **
+ ** This program is distributed in the hope that it will be useful,
+ ** but WITHOUT ANY WARRANTY; without even the implied warranty of
+ ** MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+ ** GNU Affero General Public License for more details.
+ **
+ ** You should have received a copy of the GNU Affero General Public License
+ ** along with this program. If not, see <https://www.gnu.org/licenses/>.
+ */
+
+use crate::configure::UptimeConfig;
+use crate::database::get_current_timestamp;
+use crate::datastructures::ServerLastStatus;
+use anyhow::anyhow;
+use serde_derive::{Deserialize, Serialize};
+use sqlx::SqliteConnection;
+
+const HOUR: i64 = 3600;
+const DAY: i64 = 24 * HOUR;
+const WINDOWS: [i64; 4] = [DAY, 7 * DAY, 30 * DAY, 90 * DAY];
+
+/// Availability percentage of each window, `None` if nothing is known about the window.
+#[derive(Clone, Debug, Default, Deserialize, Serialize)]
+pub struct UptimeData {
+    #[serde(rename = "24h")]
+    day: Option<f64>,
+    #[serde(rename = "7d")]
+    week: Option<f64>,
+    #[serde(rename = "30d")]
+    month: Option<f64>,
+    #[serde(rename = "90d")]
+    quarter: Option<f64>,
+}
+
+/// Downtime weight of status, unknown periods are excluded from calculation.
+fn downtime_weight(status: &str, weights: &UptimeConfig) -> Option<f64> {
+    match ServerLastStatus::try_from(status).ok()? {
+        ServerLastStatus::Optional => Some(0.0),
+        ServerLastStatus::Outage => Some(1.0),
+        ServerLastStatus::DegradedPerformance => Some(weights.degraded_performance()),
+        ServerLastStatus::PartialOutage => Some(weights.partial_outage()),
+        ServerLastStatus::Unknown => None,
+    }
+}
+
+/// Availability of `[start, end)` given a timeline of `(since, status)` sorted by time.
+fn availability(
+    timeline: &[(i64, String)],
+    start: i64,
+    end: i64,
+    weights: &UptimeConfig,
+) -> Option<f64> {
+    let mut known = 0i64;
+    let mut downtime = 0f64;
+    for (index, (since, status)) in timeline.iter().enumerate() {
+        let until = timeline
+            .get(index + 1)
+            .map(|(next, _)| *next)
+            .unwrap_or(end);
+        let (from, to) = ((*since).max(start), until.min(end));
+        if to <= from {
+            continue;
+        }
+        if let Some(weight) = downtime_weight(status, weights) {
+            known += to - from;
+            downtime += (to - from) as f64 * weight;
+        }
+    }
+    if known == 0 {
+        None
+    } else {
+        Some((1.0 - downtime / known as f64) * 100.0)
+    }
+}
+
+/// Calculate uptime of component `uuid` from the history table, only transitions are read,
+/// repeated reports of the same status do not move the timeline.
+pub async fn query_uptime(
+    conn: &mut SqliteConnection,
+    uuid: &str,
+    current_status: &str,
+    weights: &UptimeConfig,
+) -> anyhow::Result<UptimeData> {
+    let now = get_current_timestamp() as i64;
+    let since = now - WINDOWS[WINDOWS.len() - 1];
+
+    let before = sqlx::query_as::<_, (String,)>(
+        r#"SELECT "new_status" FROM "history" WHERE "uuid" = ? AND "timestamp" < ?
+            AND "old_status" != "new_status" ORDER BY "timestamp" DESC, "id" DESC LIMIT 1"#,
+    )
+    .bind(uuid)
+    .bind(since)
+    .fetch_optional(&mut *conn)
+    .await
+    .map_err(|e| anyhow!("Fetch history before {} of {} error: {:?}", since, uuid, e))?;
+
+    let transitions = sqlx::query_as::<_, (String, String, i64)>(
+        r#"SELECT "old_status", "new_status", "timestamp" FROM "history"
+            WHERE "uuid" = ? AND "timestamp" >= ? AND "old_status" != "new_status"
+            ORDER BY "timestamp", "id""#,
+    )
+    .bind(uuid)
+    .bind(since)
+    .fetch_all(&mut *conn)
+    .await
+    .map_err(|e| anyhow!("Fetch history of {} error: {:?}", uuid, e))?;
+
+    let initial = match (before, transitions.first()) {
+        (Some((status,)), _) => status,
+        (None, Some((old_status, _, _))) => old_status.clone(),
+        (None, None) => current_status.to_string(),
+    };
+    let mut timeline = vec![(since, initial)];
+    timeline.extend(
+        transitions
+            .into_iter()
+            .map(|(_, new_status, timestamp)| (timestamp, new_status)),
+    );
+
+    let mut result = WINDOWS
+        .iter()
+        .map(|window| availability(&timeline, now - window, now, weights));
+    Ok(UptimeData {
+        day: result.next().flatten(),
+        week: result.next().flatten(),
+        month: result.next().flatten(),
+        quarter: result.next().flatten(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::memory_database;
+
+    const END: i64 = 1_000 * DAY;
+
+    fn weights(context: &str) -> UptimeConfig {
+        toml::from_str(context).unwrap()
+    }
+
+    fn timeline(entries: &[(i64, &str)]) -> Vec<(i64, String)> {
+        entries
+            .iter()
+            .map(|(since, status)| (*since, status.to_string()))
+            .collect()
+    }
+
+    fn assert_close(left: Option<f64>, right: f64) {
+        let left = left.expect("availability should be known");
+        assert!((left - right).abs() < 1e-9, "{} != {}", left, right);
+    }
+
+    #[test]
+    fn test_availability_clips_to_window() {
+        let timeline = timeline(&[
+            (END - 90 * DAY, "operational"),
+            (END - 2 * DAY, "major_outage"),
+            (END - DAY, "operational"),
+        ]);
+        let weights = UptimeConfig::default();
+        let expected = [100.0, 600.0 / 7.0, 2900.0 / 30.0, 8900.0 / 90.0];
+        for (window, expected) in WINDOWS.iter().zip(expected) {
+            assert_close(
+                availability(&timeline, END - window, END, &weights),
+                expected,
+            );
+        }
+    }
+
+    #[test]
+    fn test_availability_excludes_unknown() {
+        let weights = UptimeConfig::default();
+        let half_unknown = timeline(&[(END - DAY, "unknown"), (END - DAY / 2, "operational")]);
+        assert_close(availability(&half_unknown, END - DAY, END, &weights), 100.0);
+
+        let half_outage = timeline(&[(END - DAY, "unknown"), (END - DAY / 2, "major_outage")]);
+        assert_close(availability(&half_outage, END - DAY, END, &weights), 0.0);
+
+        let all_unknown = timeline(&[(END - DAY, "unknown")]);
+        assert_eq!(availability(&all_unknown, END - DAY, END, &weights), None);
+    }
+
+    #[test]
+    fn test_availability_weights() {
+        let degraded = timeline(&[(END - DAY, "degraded_performance")]);
+        let partial = timeline(&[(END - DAY, "partial_outage")]);
+
+        let default = UptimeConfig::default();
+        assert_close(availability(&degraded, END - DAY, END, &default), 100.0);
+        assert_close(availability(&partial, END - DAY, END, &default), 70.0);
+
+        let custom = weights("degraded_performance = 0.5\npartial_outage = 0.25");
+        assert_close(availability(&degraded, END - DAY, END, &custom), 50.0);
+        assert_close(availability(&partial, END - DAY, END, &custom), 75.0);
+    }
+
+    #[tokio::test]
+    async fn test_query_uptime() {
+        let mut conn = memory_database().await;
+        let weights = UptimeConfig::default();
+
+        let data = query_uptime(&mut conn, "a", "major_outage", &weights)
+            .await
+            .unwrap();
+        assert_close(data.day, 0.0);
+        assert_close(data.quarter, 0.0);
+
+        let now = get_current_timestamp() as i64;
+        sqlx::query(
+            r#"INSERT INTO "history" ("uuid", "old_status", "new_status", "timestamp", "source")
+                VALUES ('a', 'operational', 'major_outage', ?, 'report')"#,
+        )
+        .bind(now - DAY / 2)
+        .execute(&mut conn)
+        .await
+        .unwrap();
+
+        let data = query_uptime(&mut conn, "a", "major_outage", &weights)
+            .await
+            .unwrap();
+        // Timestamps are taken in seconds, allow for a tick between insert and query.
+        assert!((data.day.unwrap() - 50.0).abs() < 0.01);
+        assert!((data.week.unwrap() - (1.0 - 0.5 / 7.0) * 100.0).abs() < 0.01);
+        assert!((data.quarter.unwrap() - (1.0 - 0.5 / 90.0) * 100.0).abs() < 0.01);
+    }
+}
@@ -1,10 +1,18 @@
 pub mod v1 {
-    use crate::configure::Component;
-    use crate::database::get_current_timestamp;
-    use crate::datastructures::{ServerLastStatus, TransferData, UpstreamTrait};
-    use axum::extract::Path;
-    use axum::http::StatusCode;
-    use axum::response::{IntoResponse, Response};
+    use crate::configure::{Component, Configure, ServerConfig, UptimeConfig};
+    use crate::database::{get_current_timestamp, hash_token, insert_history};
+    use crate::datastructures::{
+        ComponentData, DeliveryData, DeliveryQuery, DeliveryRow, HistoryData, HistoryQuery,
+        ListQuery, ServerLastStatus, StatusChange, TokenData, TransferData, Upstreams,
+    };
+    use crate::metrics::Metrics;
+    use crate::outbox::{lock_component, push_or_enqueue, Delivery};
+    use crate::uptime::query_uptime;
+    use axum::extract::{Path, Query, State};
+    use axum::http::header::{AUTHORIZATION, CONTENT_TYPE};
+    use axum::http::{HeaderMap, Method, Request, StatusCode};
+    use axum::middleware::Next;
+    use axum::response::{Html, IntoResponse, Response};
     use axum::{Json, Router};
     #[cfg(any(feature = "env_logger", feature = "log4rs"))]
     use log::error;
@@ -15,80 +23,287 @@ pub mod v1 {
     use std::sync::Arc;
     use tokio::sync::Mutex;
     use tower::ServiceBuilder;
-    use tower_http::trace::TraceLayer;
+    use tower_http::trace::{DefaultOnResponse, OnResponse, TraceLayer};
 
     pub const VERSION: &str = "1";
-    pub type FetchReturnType = (String, Option<String>, Option<String>);
+    pub type FetchReturnType = (String, Option<String>, Option<String>, Option<String>);
+    pub type FetchWithStatusReturnType = (
+        String,
+        Option<String>,
+        Option<String>,
+        Option<String>,
+        String,
+    );
 
-    pub fn make_router(conn: SqliteConnection, upstream: Box<dyn UpstreamTrait>) -> Router {
-        let conn = Arc::new(Mutex::new(conn));
-        let upstream = Arc::new(upstream);
-        Router::new()
+    pub fn make_router(
+        config: &Configure,
+        conn: Arc<Mutex<SqliteConnection>>,
+        upstreams: Upstreams,
+        metrics: Arc<Metrics>,
+    ) -> Router {
+        let server_config = config.server();
+        let config = Arc::new(config.clone());
+        let components = Arc::new(config.components().clone());
+        let uptime_config = Arc::new(config.uptime().clone());
+        let router = Router::new()
+            .route(
+                "/v1/webhooks/deliveries",
+                axum::routing::get({
+                    let conn = conn.clone();
+                    |query| async move { deliveries(query, conn).await }
+                }),
+            )
+            // Delivery errors may include receiver details, never public.
+            .route_layer(axum::middleware::from_fn_with_state(
+                server_config.clone(),
+                require_admin,
+            ))
+            .route(
+                "/v1/components",
+                axum::routing::get({
+                    let conn = conn.clone();
+                    let components = components.clone();
+                    let uptime_config = uptime_config.clone();
+                    |query| async move { list(query, components, conn, uptime_config).await }
+                }),
+            )
             .route(
                 "/v1/components/:component_id",
                 axum::routing::get({
                     let conn = conn.clone();
-                    |path| async move { get(Path(path), conn).await }
-                })
-                .post({
+                    |path| async move { get(path, conn).await }
+                }),
+            )
+            .route(
+                "/v1/components/:component_id/history",
+                axum::routing::get({
+                    let conn = conn.clone();
+                    |path, query| async move { history(path, query, conn).await }
+                }),
+            )
+            .route(
+                "/v1/components/:component_id/uptime",
+                axum::routing::get({
                     let conn = conn.clone();
-                    let upstream = upstream.clone();
-                    |path, payload| async move { post(path, payload, upstream, conn).await }
+                    |path| async move { uptime(path, conn, uptime_config).await }
+                }),
+            )
+            .route(
+                "/metrics",
+                axum::routing::get({
+                    let conn = conn.clone();
+                    let components = components.clone();
+                    let metrics = metrics.clone();
+                    || async move { render_metrics(metrics, components, conn).await }
+                }),
+            )
+            .route_layer(axum::middleware::from_fn_with_state(
+                server_config.clone(),
+                require_auth,
+            ))
+            // Reports and token rotation are authorized inside the handlers, either by the
+            // component token or by the shared auth_header.
+            .route(
+                "/v1/components/:component_id/token",
+                axum::routing::put({
+                    let conn = conn.clone();
+                    let server_config = server_config.clone();
+                    |path, headers, payload| async move {
+                        put_token(path, headers, payload, conn, server_config).await
+                    }
+                }),
+            )
+            .route(
+                "/v1/components/:component_id",
+                axum::routing::post({
+                    let conn = conn.clone();
+                    let upstreams = upstreams.clone();
+                    let metrics = metrics.clone();
+                    |path, headers, payload| async move {
+                        post(path, headers, payload, upstreams, conn, config, metrics).await
+                    }
                 }),
             )
             .route(
                 "/",
                 axum::routing::get(|| async { Json(json!({ "version": VERSION, "status": 200 })) }),
+            );
+        let router = if server_config.public_status_page() {
+            router.route(
+                "/status",
+                axum::routing::get({
+                    let conn = conn.clone();
+                    || async move { status_page(components, conn).await }
+                }),
             )
-            .layer(ServiceBuilder::new().layer(TraceLayer::new_for_http()))
+        } else {
+            router
+        };
+        router.layer(
+            ServiceBuilder::new().layer(TraceLayer::new_for_http().on_response(
+                move |response: &Response<_>, latency, span: &tracing::Span| {
+                    metrics.observe_request(response.status().as_u16(), latency);
+                    DefaultOnResponse::default().on_response(response, latency, span)
+                },
+            )),
+        )
+    }
+
+    /// Reject requests without the configured `auth_header` secret, accepted either
+    /// as `Authorization: Bearer <secret>` or as the raw `Authorization` value.
+    ///
+    /// Read-only requests skip the check when `public_status_page` is enabled.
+    pub async fn require_auth<B>(
+        State(server_config): State<ServerConfig>,
+        req: Request<B>,
+        next: Next<B>,
+    ) -> Response {
+        let secret = server_config.auth_header();
+        if secret.is_empty()
+            || (server_config.public_status_page()
+                && matches!(*req.method(), Method::GET | Method::HEAD))
+        {
+            return next.run(req).await;
+        }
+
+        if !is_admin(req.headers(), &secret) {
+            return unauthorized();
+        }
+        next.run(req).await
+    }
+
+    /// Reject requests without the configured `auth_header` secret, even read-only requests
+    /// when `public_status_page` is enabled.
+    pub async fn require_admin<B>(
+        State(server_config): State<ServerConfig>,
+        req: Request<B>,
+        next: Next<B>,
+    ) -> Response {
+        let secret = server_config.auth_header();
+        if !secret.is_empty() && !is_admin(req.headers(), &secret) {
+            return unauthorized();
+        }
+        next.run(req).await
+    }
+
+    fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+        a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+    }
+
+    /// Whether the request presents the configured `auth_header`, never if it is empty.
+    fn is_admin(headers: &HeaderMap, auth_header: &str) -> bool {
+        !auth_header.is_empty()
+            && presented_secret(headers)
+                .is_some_and(|secret| constant_time_eq(secret.as_bytes(), auth_header.as_bytes()))
+    }
+
+    /// Whether the request presents the token hashed as `token_hash`, never if it is empty.
+    fn holds_token(headers: &HeaderMap, token_hash: &str) -> bool {
+        !token_hash.is_empty()
+            && presented_secret(headers).is_some_and(|secret| {
+                constant_time_eq(hash_token(secret).as_bytes(), token_hash.as_bytes())
+            })
+    }
+
+    fn presented_secret(headers: &HeaderMap) -> Option<&str> {
+        headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.strip_prefix("Bearer ").unwrap_or(value))
+    }
+
+    fn unauthorized() -> Response {
+        (StatusCode::UNAUTHORIZED, Json(json!({"status": 401}))).into_response()
     }
 
     pub async fn post(
         Path(uuid): Path<String>,
-        Json(payload): Json<TransferData>,
-        upstream: Arc<Box<dyn UpstreamTrait>>,
+        headers: HeaderMap,
+        body: String,
+        upstreams: Upstreams,
         sql_conn: Arc<Mutex<SqliteConnection>>,
+        config: Arc<Configure>,
+        metrics: Arc<Metrics>,
     ) -> impl IntoResponse {
-        let last_status = ServerLastStatus::try_from(payload.status())
-            .map_err(|e| error!("Got error while read data: {:?}", e));
+        // Held until the change is pushed, so a concurrent report is pushed after this one.
+        let guard = lock_component(&uuid).await;
+        let mut conn = sql_conn.lock().await;
 
-        let last_status = match last_status {
-            Ok(status) => status,
+        let ret = sqlx::query_as::<_, FetchWithStatusReturnType>(
+            r#"SELECT "uuid", "page", "component_id", "token", "status" FROM "machines" WHERE "uuid" = ?"#,
+        )
+        .bind(&uuid)
+        .fetch_optional(&mut *conn)
+        .await
+        .map_err(|e| error!("Fetch {} component error: {:?}", &uuid, e));
+
+        let row = match ret {
+            Ok(row) => row.map(|(uuid, page, component_id, token, status)| {
+                (Component::from((uuid, page, component_id, token)), status)
+            }),
             Err(_) => {
                 return (
                     StatusCode::INTERNAL_SERVER_ERROR,
                     json!({"status": 500}).to_string(),
                 )
-                    .into_response()
+                    .into_response();
             }
         };
 
-        let mut sql_conn = sql_conn.lock().await;
+        // A component token binds the report to this component only, components without
+        // one fall back to the shared auth_header. Unknown components are checked against
+        // auth_header as well, so the answer does not tell whether a component exists.
+        let auth_header = config.server().auth_header();
+        let authorized = match row {
+            Some((ref component, _)) if !component.token().is_empty() => {
+                holds_token(&headers, component.token())
+            }
+            _ => auth_header.is_empty() || is_admin(&headers, &auth_header),
+        };
+        if !authorized {
+            error!("Reject unauthorized report for component {}", &uuid);
+            return unauthorized();
+        }
 
-        let ret = sqlx::query_as::<_, FetchReturnType>(
-            r#"SELECT "uuid", "page", "component_id" FROM "matchines" WHERE "uuid" = ?"#,
-        )
-        .bind(&uuid)
-        .fetch_optional(&mut *sql_conn)
-        .await
-        .map_err(|e| error!("Fetch {} component error: {:?}", &uuid, e))
-        .map(|r| {
-            if r.is_none() {
-                error!("Fetch component {} is null", &uuid)
+        let (component, previous_status) = match row {
+            Some(row) => row,
+            None => {
+                error!("Reject report for unknown component {}", &uuid);
+                return (
+                    StatusCode::NOT_FOUND,
+                    serde_json::to_string(&TransferData::not_found()).unwrap(),
+                )
+                    .into_response();
             }
-            r
-        });
+        };
 
-        let component = match ret {
-            Ok(Some(ret)) => Component::from(ret),
-            _ => {
+        let payload = match serde_json::from_str::<TransferData>(&body) {
+            Ok(payload) => payload,
+            Err(e) => {
+                error!("Reject malformed report for {}: {:?}", &uuid, e);
+                return (StatusCode::BAD_REQUEST, json!({"status": 400}).to_string())
+                    .into_response();
+            }
+        };
+        let last_status = ServerLastStatus::try_from(payload.status())
+            .map_err(|e| error!("Got error while read data: {:?}", e));
+
+        let last_status = match last_status {
+            Ok(ServerLastStatus::Unknown) => {
+                error!("Reject unknown status {:?} for {}", payload.status(), &uuid);
+                return (StatusCode::BAD_REQUEST, json!({"status": 400}).to_string())
+                    .into_response();
+            }
+            Ok(status) => status,
+            Err(_) => {
                 return (
                     StatusCode::INTERNAL_SERVER_ERROR,
                     json!({"status": 500}).to_string(),
                 )
-                    .into_response();
+                    .into_response()
             }
         };
+        metrics.observe_report(&uuid);
 
         let query_ret = sqlx::query(
             r#"UPDATE "machines" SET "status" = ?, "last_update" = ? WHERE "uuid" = ?"#,
@@ -96,7 +311,7 @@ pub mod v1 {
         .bind(payload.status())
         .bind(get_current_timestamp() as u32)
         .bind(&uuid)
-        .execute(&mut *sql_conn)
+        .execute(&mut *conn)
         .await
         .map_err(|e| {
             error!(
@@ -107,13 +322,44 @@ pub mod v1 {
             )
         });
 
-        let upstream_ret = upstream
-            .set_component_status(component.report_id(), component.page(), last_status.into())
-            .await
-            .map_err(|e| error!("Got error while upload status to server: {:?}", e));
+        let history_ret = insert_history(
+            &mut conn,
+            &uuid,
+            &previous_status,
+            payload.status(),
+            "report",
+        )
+        .await
+        .map_err(|e| error!("{:?}", e));
+
+        drop(conn);
+
+        // Unchanged status is left to the periodic resync to save upstream API quota.
+        let upstream_ret = if previous_status != payload.status() {
+            let component = config.component(&uuid).unwrap_or(&component);
+            let change = StatusChange::new(
+                component,
+                ServerLastStatus::try_from(&previous_status).unwrap_or(ServerLastStatus::Unknown),
+                last_status,
+                get_current_timestamp(),
+            );
+            push_or_enqueue(&sql_conn, &upstreams, &metrics, &change, &guard)
+                .await
+                .map_err(|e| error!("Got error while upload status to server: {:?}", e))
+        } else {
+            Ok(Delivery::Delivered)
+        };
 
-        if query_ret.is_ok() && upstream_ret.is_ok() {
+        if query_ret.is_err() || history_ret.is_err() {
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                json!({"status": 500}).to_string(),
+            )
+        } else if let Ok(Delivery::Delivered) = upstream_ret {
             (StatusCode::OK, json!({"status": 200}).to_string())
+        } else if let Ok(Delivery::Queued) = upstream_ret {
+            // Upstream push will be retried by the outbox worker.
+            (StatusCode::ACCEPTED, json!({"status": 202}).to_string())
         } else {
             (
                 StatusCode::INTERNAL_SERVER_ERROR,
@@ -123,6 +369,404 @@ pub mod v1 {
         .into_response()
     }
 
+    pub async fn history(
+        Path(uuid): Path<String>,
+        Query(query): Query<HistoryQuery>,
+        sql_conn: Arc<Mutex<SqliteConnection>>,
+    ) -> Response {
+        let mut sql_conn = sql_conn.lock().await;
+        let exists = sqlx::query_as::<_, (i32,)>(r#"SELECT 1 FROM "machines" WHERE "uuid" = ?"#)
+            .bind(&uuid)
+            .fetch_optional(&mut *sql_conn)
+            .await;
+        match exists {
+            Ok(Some(_)) => {}
+            Ok(None) => {
+                return (
+                    StatusCode::NOT_FOUND,
+                    serde_json::to_string(&TransferData::not_found()).unwrap(),
+                )
+                    .into_response()
+            }
+            Err(e) => {
+                error!("Got error while fetching component {}: {:?}", &uuid, e);
+                return (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    json!({"status": 500}).to_string(),
+                )
+                    .into_response();
+            }
+        }
+
+        let rows = sqlx::query_as::<_, (String, String, i64, String)>(
+            r#"SELECT "old_status", "new_status", "timestamp", "source" FROM "history"
+                WHERE "uuid" = ? AND "timestamp" >= ? AND "timestamp" <= ?
+                ORDER BY "timestamp", "id""#,
+        )
+        .bind(&uuid)
+        .bind(query.since())
+        .bind(query.until())
+        .fetch_all(&mut *sql_conn)
+        .await;
+
+        match rows {
+            Ok(rows) => (
+                StatusCode::OK,
+                Json(json!({
+                    "status": 200,
+                    "history": rows.into_iter().map(HistoryData::from).collect::<Vec<_>>(),
+                })),
+            )
+                .into_response(),
+            Err(e) => {
+                error!(
+                    "Got error while fetching component {} history: {:?}",
+                    &uuid, e
+                );
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    json!({"status": 500}).to_string(),
+                )
+                    .into_response()
+            }
+        }
+    }
+
+    pub async fn deliveries(
+        Query(query): Query<DeliveryQuery>,
+        sql_conn: Arc<Mutex<SqliteConnection>>,
+    ) -> Response {
+        let mut sql_conn = sql_conn.lock().await;
+        let rows = sqlx::query_as::<_, DeliveryRow>(
+            r#"SELECT "delivery_id", "upstream", "uuid", "new_status", "attempt", "status_code",
+                "error", "timestamp" FROM "webhook_deliveries"
+                WHERE (?1 IS NULL OR "upstream" = ?1) AND (?2 IS NULL OR "uuid" = ?2)
+                AND (?3 IS NULL OR "delivery_id" = ?3)
+                ORDER BY "id" DESC LIMIT ?4"#,
+        )
+        .bind(query.upstream())
+        .bind(query.uuid())
+        .bind(query.delivery_id())
+        .bind(query.limit())
+        .fetch_all(&mut *sql_conn)
+        .await;
+
+        match rows {
+            Ok(rows) => (
+                StatusCode::OK,
+                Json(json!({
+                    "status": 200,
+                    "deliveries": rows.into_iter().map(DeliveryData::from).collect::<Vec<_>>(),
+                })),
+            )
+                .into_response(),
+            Err(e) => {
+                error!("Got error while fetching webhook deliveries: {:?}", e);
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    json!({"status": 500}).to_string(),
+                )
+                    .into_response()
+            }
+        }
+    }
+
+    pub async fn uptime(
+        Path(uuid): Path<String>,
+        sql_conn: Arc<Mutex<SqliteConnection>>,
+        uptime_config: Arc<UptimeConfig>,
+    ) -> Response {
+        let mut sql_conn = sql_conn.lock().await;
+        let status =
+            sqlx::query_as::<_, (String,)>(r#"SELECT "status" FROM "machines" WHERE "uuid" = ?"#)
+                .bind(&uuid)
+                .fetch_optional(&mut *sql_conn)
+                .await;
+        let status = match status {
+            Ok(Some((status,))) => status,
+            Ok(None) => {
+                return (
+                    StatusCode::NOT_FOUND,
+                    serde_json::to_string(&TransferData::not_found()).unwrap(),
+                )
+                    .into_response()
+            }
+            Err(e) => {
+                error!("Got error while fetching component {}: {:?}", &uuid, e);
+                return (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    json!({"status": 500}).to_string(),
+                )
+                    .into_response();
+            }
+        };
+
+        match query_uptime(&mut sql_conn, &uuid, &status, &uptime_config).await {
+            Ok(uptime) => (
+                StatusCode::OK,
+                Json(json!({"status": 200, "uptime": uptime})),
+            )
+                .into_response(),
+            Err(e) => {
+                error!("Got error while calculating uptime: {:?}", e);
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    json!({"status": 500}).to_string(),
+                )
+                    .into_response()
+            }
+        }
+    }
+
+    /// Rotate the token of a component, authorized by `auth_header` or the current token.
+    ///
+    /// Rotation is refused if `auth_header` is not configured.
+    pub async fn put_token(
+        Path(uuid): Path<String>,
+        headers: HeaderMap,
+        body: String,
+        sql_conn: Arc<Mutex<SqliteConnection>>,
+        server_config: ServerConfig,
+    ) -> Response {
+        let mut sql_conn = sql_conn.lock().await;
+        let current = sqlx::query_as::<_, (Option<String>,)>(
+            r#"SELECT "token" FROM "machines" WHERE "uuid" = ?"#,
+        )
+        .bind(&uuid)
+        .fetch_optional(&mut *sql_conn)
+        .await;
+        let current = match current {
+            Ok(current) => current.map(|(current,)| current.unwrap_or_default()),
+            Err(e) => {
+                error!("Got error while fetching component {}: {:?}", &uuid, e);
+                return (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    json!({"status": 500}).to_string(),
+                )
+                    .into_response();
+            }
+        };
+        // Holders of the current token rotate it themselves, the shared auth_header
+        // may set any token but never matches if it is empty.
+        let authorized = is_admin(&headers, &server_config.auth_header())
+            || current
+                .as_deref()
+                .is_some_and(|current| holds_token(&headers, current));
+        if !authorized {
+            error!("Reject unauthorized token rotation of component {}", &uuid);
+            return unauthorized();
+        }
+        if current.is_none() {
+            return (
+                StatusCode::NOT_FOUND,
+                serde_json::to_string(&TransferData::not_found()).unwrap(),
+            )
+                .into_response();
+        }
+
+        let payload = match serde_json::from_str::<TokenData>(&body) {
+            Ok(payload) => payload,
+            Err(e) => {
+                error!("Reject malformed token of {}: {:?}", &uuid, e);
+                return (StatusCode::BAD_REQUEST, json!({"status": 400}).to_string())
+                    .into_response();
+            }
+        };
+        let token = if payload.token().is_empty() {
+            None
+        } else {
+            Some(hash_token(payload.token()))
+        };
+        match sqlx::query(r#"UPDATE "machines" SET "token" = ? WHERE "uuid" = ?"#)
+            .bind(token)
+            .bind(&uuid)
+            .execute(&mut *sql_conn)
+            .await
+        {
+            Ok(_) => (StatusCode::OK, json!({"status": 200}).to_string()),
+            Err(e) => {
+                error!("Update token for component {} error: {:?}", &uuid, e);
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    json!({"status": 500}).to_string(),
+                )
+            }
+        }
+        .into_response()
+    }
+
+    pub async fn list(
+        Query(query): Query<ListQuery>,
+        components: Arc<Vec<Component>>,
+        sql_conn: Arc<Mutex<SqliteConnection>>,
+        uptime_config: Arc<UptimeConfig>,
+    ) -> Response {
+        let mut sql_conn = sql_conn.lock().await;
+        let rows = match sqlx::query_as::<_, (String, String, i64, Option<String>, Option<String>)>(
+            r#"SELECT "uuid", "status", "last_update", "page", "component_id" FROM "machines""#,
+        )
+        .fetch_all(&mut *sql_conn)
+        .await
+        {
+            Ok(rows) => rows,
+            Err(e) => {
+                error!("Got error while listing components: {:?}", e);
+                return (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    json!({"status": 500}).to_string(),
+                )
+                    .into_response();
+            }
+        };
+
+        let mut result = Vec::new();
+        for (uuid, status, last_update, page, identity_id) in rows {
+            if !query.matches(&status) {
+                continue;
+            }
+            let uptime = match query_uptime(&mut sql_conn, &uuid, &status, &uptime_config).await {
+                Ok(uptime) => uptime,
+                Err(e) => {
+                    error!("Got error while calculating uptime: {:?}", e);
+                    return (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        json!({"status": 500}).to_string(),
+                    )
+                        .into_response();
+                }
+            };
+            let data = match components.iter().find(|c| c.uuid() == uuid) {
+                Some(component) => ComponentData::new(
+                    uuid,
+                    component.name().to_string(),
+                    component.page().to_string(),
+                    component.report_id().to_string(),
+                    status,
+                    last_update,
+                ),
+                None => ComponentData::new(
+                    uuid,
+                    String::new(),
+                    page.unwrap_or_default(),
+                    identity_id.unwrap_or_default(),
+                    status,
+                    last_update,
+                ),
+            };
+            result.push(data.with_uptime(uptime));
+        }
+
+        (
+            StatusCode::OK,
+            Json(json!({"status": 200, "components": result})),
+        )
+            .into_response()
+    }
+
+    pub async fn render_metrics(
+        metrics: Arc<Metrics>,
+        components: Arc<Vec<Component>>,
+        sql_conn: Arc<Mutex<SqliteConnection>>,
+    ) -> Response {
+        let mut sql_conn = sql_conn.lock().await;
+        match metrics.render(&mut sql_conn, &components).await {
+            Ok(body) => (
+                StatusCode::OK,
+                [(CONTENT_TYPE, "text/plain; version=0.0.4")],
+                body,
+            )
+                .into_response(),
+            Err(e) => {
+                error!("Got error while rendering metrics: {:?}", e);
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    json!({"status": 500}).to_string(),
+                )
+                    .into_response()
+            }
+        }
+    }
+
+    pub async fn status_page(
+        components: Arc<Vec<Component>>,
+        sql_conn: Arc<Mutex<SqliteConnection>>,
+    ) -> Response {
+        let mut sql_conn = sql_conn.lock().await;
+        let rows = match sqlx::query_as::<_, (String, String, i64)>(
+            r#"SELECT "uuid", "status", "last_update" FROM "machines""#,
+        )
+        .fetch_all(&mut *sql_conn)
+        .await
+        {
+            Ok(rows) => rows,
+            Err(e) => {
+                error!("Got error while fetching status page components: {:?}", e);
+                return (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    json!({"status": 500}).to_string(),
+                )
+                    .into_response();
+            }
+        };
+        Html(render_status_page(&components, &rows)).into_response()
+    }
+
+    fn render_status_page(components: &[Component], rows: &[(String, String, i64)]) -> String {
+        let mut body = String::new();
+        for component in components {
+            let row = rows.iter().find(|(uuid, _, _)| uuid == component.uuid());
+            let status = row
+                .and_then(|(_, status, _)| ServerLastStatus::try_from(status).ok())
+                .unwrap_or(ServerLastStatus::Unknown);
+            let last_update = row
+                .and_then(|(_, _, last_update)| {
+                    chrono::NaiveDateTime::from_timestamp_opt(*last_update, 0)
+                })
+                .map(|time| time.format("%Y-%m-%d %H:%M:%S UTC").to_string())
+                .unwrap_or_else(|| "never".to_string());
+            body.push_str(&format!(
+                r#"<tr><td><span class="dot" style="background:{colour}"></span>{name}</td><td>{status}</td><td>{last_update}</td></tr>
+"#,
+                colour = status.colour(),
+                name = escape_html(component.name()),
+                status = status.to_string().replace('_', " "),
+                last_update = last_update,
+            ));
+        }
+        format!(
+            r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>Status</title>
+<style>
+body {{ font-family: sans-serif; max-width: 48em; margin: 2em auto; padding: 0 1em; color: #333; }}
+table {{ width: 100%; border-collapse: collapse; }}
+td, th {{ text-align: left; padding: .6em; border-bottom: 1px solid #eee; }}
+.dot {{ display: inline-block; width: .8em; height: .8em; border-radius: 50%; margin-right: .6em; }}
+</style>
+</head>
+<body>
+<h1>Status</h1>
+<table>
+<tr><th>Component</th><th>Status</th><th>Last update</th></tr>
+{body}
+</table>
+</body>
+</html>
+"#,
+            body = body
+        )
+    }
+
+    pub fn escape_html(s: &str) -> String {
+        s.replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+            .replace('"', "&quot;")
+    }
+
     pub async fn get(Path(uuid): Path<String>, sql_conn: Arc<Mutex<SqliteConnection>>) -> Response {
         let mut sql_conn = sql_conn.lock().await;
         let query_result =
@@ -155,7 +799,320 @@ pub mod v1 {
         }
         .into_response()
     }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use crate::test_util::{configure, memory_database};
+        use axum::body::Body;
+        use tower::ServiceExt;
+
+        async fn router(server: &str) -> (Router, Arc<Mutex<SqliteConnection>>) {
+            let config = configure(&format!(
+                "[server]\naddr = \"127.0.0.1\"\nport = 41132\n{}\n",
+                server
+            ));
+            let conn = Arc::new(Mutex::new(memory_database().await));
+            let router = make_router(
+                &config,
+                conn.clone(),
+                Arc::new(Vec::new()),
+                Arc::new(Metrics::new().unwrap()),
+            );
+            (router, conn)
+        }
+
+        async fn request(
+            router: &Router,
+            method: Method,
+            uri: &str,
+            authorization: Option<&str>,
+            body: &str,
+        ) -> StatusCode {
+            let mut request = Request::builder()
+                .method(method)
+                .uri(uri)
+                .header(CONTENT_TYPE, "application/json");
+            if let Some(authorization) = authorization {
+                request = request.header(AUTHORIZATION, authorization);
+            }
+            router
+                .clone()
+                .oneshot(request.body(Body::from(body.to_string())).unwrap())
+                .await
+                .unwrap()
+                .status()
+        }
+
+        async fn insert_component(conn: &Mutex<SqliteConnection>, uuid: &str, token: &str) {
+            sqlx::query(
+                r#"INSERT INTO "machines"
+                    ("uuid", "status", "last_update", "need_push", "page", "component_id", "token")
+                    VALUES (?, 'unknown', 0, 1, '', '', ?)"#,
+            )
+            .bind(uuid)
+            .bind(token)
+            .execute(&mut *conn.lock().await)
+            .await
+            .unwrap();
+        }
+
+        const REPORT: &str = r#"{"status": "operational"}"#;
+
+        #[tokio::test]
+        async fn test_require_secret() {
+            let (router, conn) =
+                router("auth_header = \"secret\"\npublic_status_page = false").await;
+            insert_component(&conn, "a", "").await;
+
+            for uri in ["/v1/components", "/v1/components/a", "/metrics"] {
+                assert_eq!(
+                    request(&router, Method::GET, uri, None, "").await,
+                    StatusCode::UNAUTHORIZED
+                );
+                assert_eq!(
+                    request(&router, Method::GET, uri, Some("wrong"), "").await,
+                    StatusCode::UNAUTHORIZED
+                );
+                assert_eq!(
+                    request(&router, Method::GET, uri, Some("Bearer secret"), "").await,
+                    StatusCode::OK
+                );
+            }
+            // Raw header value is accepted as well.
+            assert_eq!(
+                request(&router, Method::GET, "/v1/components", Some("secret"), "").await,
+                StatusCode::OK
+            );
+            assert_eq!(
+                request(&router, Method::POST, "/v1/components/a", None, REPORT).await,
+                StatusCode::UNAUTHORIZED
+            );
+            assert_eq!(
+                request(
+                    &router,
+                    Method::POST,
+                    "/v1/components/a",
+                    Some("Bearer secret"),
+                    REPORT
+                )
+                .await,
+                StatusCode::OK
+            );
+        }
+
+        #[tokio::test]
+        async fn test_public_status_page() {
+            let (router, conn) =
+                router("auth_header = \"secret\"\npublic_status_page = true").await;
+            insert_component(&conn, "a", "").await;
+
+            assert_eq!(
+                request(&router, Method::GET, "/v1/components/a", None, "").await,
+                StatusCode::OK
+            );
+            assert_eq!(
+                request(&router, Method::GET, "/status", None, "").await,
+                StatusCode::OK
+            );
+            // Reports still need the secret.
+            assert_eq!(
+                request(&router, Method::POST, "/v1/components/a", None, REPORT).await,
+                StatusCode::UNAUTHORIZED
+            );
+        }
+
+        #[tokio::test]
+        async fn test_post_checks_secret_first() {
+            let (router, conn) =
+                router("auth_header = \"secret\"\npublic_status_page = false").await;
+            insert_component(&conn, "a", "").await;
+
+            // Neither existence of the component nor the body is looked at without the secret.
+            for (uri, body) in [
+                ("/v1/components/a", "not json"),
+                ("/v1/components/missing", REPORT),
+            ] {
+                assert_eq!(
+                    request(&router, Method::POST, uri, None, body).await,
+                    StatusCode::UNAUTHORIZED
+                );
+            }
+            let secret = Some("Bearer secret");
+            assert_eq!(
+                request(
+                    &router,
+                    Method::POST,
+                    "/v1/components/a",
+                    secret,
+                    "not json"
+                )
+                .await,
+                StatusCode::BAD_REQUEST
+            );
+            assert_eq!(
+                request(
+                    &router,
+                    Method::POST,
+                    "/v1/components/missing",
+                    secret,
+                    REPORT
+                )
+                .await,
+                StatusCode::NOT_FOUND
+            );
+        }
+
+        #[tokio::test]
+        async fn test_component_token() {
+            let (router, conn) =
+                router("auth_header = \"secret\"\npublic_status_page = false").await;
+            insert_component(&conn, "a", &hash_token("token-a")).await;
+            insert_component(&conn, "b", &hash_token("token-b")).await;
+
+            let post = |uri, authorization| {
+                let router = router.clone();
+                async move { request(&router, Method::POST, uri, authorization, REPORT).await }
+            };
+            assert_eq!(
+                post("/v1/components/a", Some("Bearer token-a")).await,
+                StatusCode::OK
+            );
+            // A token is bound to its own component.
+            assert_eq!(
+                post("/v1/components/b", Some("Bearer token-a")).await,
+                StatusCode::UNAUTHORIZED
+            );
+            // The shared secret no longer reports for a component with a token.
+            assert_eq!(
+                post("/v1/components/a", Some("Bearer secret")).await,
+                StatusCode::UNAUTHORIZED
+            );
+        }
+
+        #[tokio::test]
+        async fn test_rotate_token() {
+            // Without auth_header only the holder of the current token may rotate it.
+            let (router, conn) = router("public_status_page = false").await;
+            insert_component(&conn, "a", &hash_token("old")).await;
+            insert_component(&conn, "b", "").await;
+            let rotate = |uri, authorization| {
+                let router = router.clone();
+                async move {
+                    request(
+                        &router,
+                        Method::PUT,
+                        uri,
+                        authorization,
+                        r#"{"token": "new"}"#,
+                    )
+                    .await
+                }
+            };
+
+            assert_eq!(
+                rotate("/v1/components/a/token", None).await,
+                StatusCode::UNAUTHORIZED
+            );
+            assert_eq!(
+                rotate("/v1/components/b/token", None).await,
+                StatusCode::UNAUTHORIZED
+            );
+            assert_eq!(
+                rotate("/v1/components/a/token", Some("Bearer old")).await,
+                StatusCode::OK
+            );
+            assert_eq!(
+                request(
+                    &router,
+                    Method::POST,
+                    "/v1/components/a",
+                    Some("Bearer old"),
+                    REPORT
+                )
+                .await,
+                StatusCode::UNAUTHORIZED
+            );
+            assert_eq!(
+                request(
+                    &router,
+                    Method::POST,
+                    "/v1/components/a",
+                    Some("Bearer new"),
+                    REPORT
+                )
+                .await,
+                StatusCode::OK
+            );
+        }
+
+        #[tokio::test]
+        async fn test_admin_sets_token() {
+            let (router, conn) =
+                router("auth_header = \"secret\"\npublic_status_page = false").await;
+            insert_component(&conn, "a", "").await;
+            let body = r#"{"token": "new"}"#;
+
+            assert_eq!(
+                request(&router, Method::PUT, "/v1/components/a/token", None, body).await,
+                StatusCode::UNAUTHORIZED
+            );
+            assert_eq!(
+                request(
+                    &router,
+                    Method::PUT,
+                    "/v1/components/missing/token",
+                    None,
+                    body
+                )
+                .await,
+                StatusCode::UNAUTHORIZED
+            );
+            let secret = Some("Bearer secret");
+            assert_eq!(
+                request(
+                    &router,
+                    Method::PUT,
+                    "/v1/components/missing/token",
+                    secret,
+                    body
+                )
+                .await,
+                StatusCode::NOT_FOUND
+            );
+            assert_eq!(
+                request(&router, Method::PUT, "/v1/components/a/token", secret, body).await,
+                StatusCode::OK
+            );
+            let (token,) = sqlx::query_as::<_, (String,)>(
+                r#"SELECT "token" FROM "machines" WHERE "uuid" = 'a'"#,
+            )
+            .fetch_one(&mut *conn.lock().await)
+            .await
+            .unwrap();
+            // Only the hash is stored.
+            assert_eq!(token, hash_token("new"));
+        }
+
+        #[tokio::test]
+        async fn test_deliveries_require_secret() {
+            let (router, _) = router("auth_header = \"secret\"\npublic_status_page = true").await;
+            let uri = "/v1/webhooks/deliveries";
+            assert_eq!(
+                request(&router, Method::GET, uri, None, "").await,
+                StatusCode::UNAUTHORIZED
+            );
+            assert_eq!(
+                request(&router, Method::GET, uri, Some("Bearer secret"), "").await,
+                StatusCode::OK
+            );
+            // Other read-only endpoints stay public.
+            assert_eq!(
+                request(&router, Method::GET, "/v1/components", None, "").await,
+                StatusCode::OK
+            );
+        }
+    }
 }
 
-pub use current::VERSION as CURRENT_VERSION;
 pub use v1 as current;